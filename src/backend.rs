@@ -0,0 +1,174 @@
+use std::{
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicIsize, Ordering},
+    },
+    task::{Context, Poll, Waker},
+};
+
+use crate::{
+    config::Config,
+    models::{ClipboardEntry, ClipboardItem, RawItem, SourceApp},
+};
+
+#[cfg(target_os = "macos")]
+mod macos;
+mod memory;
+#[cfg(target_os = "linux")]
+mod mime;
+#[cfg(target_os = "linux")]
+mod wayland;
+#[cfg(target_os = "linux")]
+mod x11;
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests;
+
+#[cfg(target_os = "macos")]
+pub use macos::MacosBackend;
+pub use memory::MemoryBackend;
+#[cfg(target_os = "linux")]
+pub use wayland::WaylandBackend;
+#[cfg(target_os = "linux")]
+pub use x11::X11Backend;
+
+/// Markers apps add to the clipboard to tell clipboard managers how to treat the data,
+/// see <http://nspasteboard.org>
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ClipboardMarkers {
+    /// Sensitive data such as passwords
+    pub concealed: bool,
+    /// Data only on the clipboard briefly, e.g. to perform a paste
+    pub transient: bool,
+    /// Data the app generated rather than the user copying it
+    pub auto_generated: bool,
+}
+
+#[cfg(target_os = "macos")]
+impl ClipboardMarkers {
+    pub const CONCEALED_TYPE: &str = "org.nspasteboard.ConcealedType";
+    pub const TRANSIENT_TYPE: &str = "org.nspasteboard.TransientType";
+    pub const AUTO_GENERATED_TYPE: &str = "org.nspasteboard.AutoGeneratedType";
+
+    pub fn from_types<'a>(types: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut markers = Self::default();
+        for t in types {
+            match t {
+                Self::CONCEALED_TYPE => markers.concealed = true,
+                Self::TRANSIENT_TYPE => markers.transient = true,
+                Self::AUTO_GENERATED_TYPE => markers.auto_generated = true,
+                _ => {}
+            }
+        }
+        markers
+    }
+}
+
+/// How a backend lets the monitor know the clipboard changed
+pub enum ChangeSource {
+    /// `change_count` has to be checked periodically
+    Poll,
+    /// Changes are announced through the signal as they happen
+    Push(Arc<ChangeSignal>),
+}
+
+/// Counter bumped from the threads backends learn about clipboard events on, waking the task
+/// waiting on it
+#[derive(Default)]
+pub struct ChangeSignal {
+    count: AtomicIsize,
+    waker: Mutex<Option<Waker>>,
+}
+
+impl ChangeSignal {
+    pub fn count(&self) -> isize {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    /// Records an event, called from whichever thread the backend learns about it on
+    pub fn notify(&self) {
+        self.count.fetch_add(1, Ordering::SeqCst);
+        if let Some(waker) = self.waker.lock().unwrap().take() {
+            waker.wake();
+        }
+    }
+
+    /// Ready once the count moved past `count`, only the latest task to poll is woken
+    pub fn poll_changed(&self, cx: &mut Context<'_>, count: isize) -> Poll<()> {
+        // registered before checking so a change in between isn't missed
+        *self.waker.lock().unwrap() = Some(cx.waker().clone());
+        if self.count() != count {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// Access to the system clipboard, so the monitor and view don't depend on a specific platform api
+pub trait ClipboardBackend: Send + Sync {
+    /// Counter that changes every time the clipboard contents are replaced
+    fn change_count(&self) -> isize;
+
+    /// How changes to `change_count` are found out about
+    fn change_source(&self) -> ChangeSource {
+        ChangeSource::Poll
+    }
+
+    /// Bumped every time another app pastes what `write_entry` put on the clipboard, `None` where
+    /// pastes can't be seen
+    fn paste_signal(&self) -> Option<Arc<ChangeSignal>> {
+        None
+    }
+
+    /// Reads the current clipboard contents, `None` if the clipboard could not be read
+    fn read_items(&self) -> Option<Vec<ClipboardItem>>;
+
+    /// Reads every type and its data on the current clipboard, empty if the backend can't
+    fn read_raw(&self) -> Vec<RawItem> {
+        Vec::new()
+    }
+
+    /// Reads the markers on the current clipboard contents
+    fn read_markers(&self) -> ClipboardMarkers {
+        ClipboardMarkers::default()
+    }
+
+    /// The frontmost application, taken to be the one that made the latest copy
+    fn frontmost_app(&self) -> Option<SourceApp> {
+        None
+    }
+
+    /// Replaces the clipboard contents with those of `entry`, its raw items where there are any
+    fn write_entry(&self, entry: &ClipboardEntry);
+}
+
+pub type Backend = Arc<dyn ClipboardBackend>;
+
+#[cfg(target_os = "macos")]
+pub fn default_backend(_config: &Config) -> Backend {
+    Arc::new(MacosBackend::default())
+}
+
+/// wlr-data-control under Wayland, falling back to X11 for other compositors through XWayland
+#[cfg(target_os = "linux")]
+pub fn default_backend(config: &Config) -> Backend {
+    if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        match WaylandBackend::connect(config.primary_selection) {
+            Ok(backend) => return Arc::new(backend),
+            Err(err) => eprintln!("{err}, trying x11"),
+        }
+    }
+    match X11Backend::connect(config.primary_selection) {
+        Ok(backend) => Arc::new(backend),
+        Err(err) => {
+            eprintln!("{err}, clipboard changes won't be recorded");
+            Arc::new(MemoryBackend::new())
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+pub fn default_backend(_config: &Config) -> Backend {
+    Arc::new(MemoryBackend::new())
+}
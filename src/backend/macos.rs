@@ -0,0 +1,435 @@
+use crate::{
+    backend::{ChangeSignal, ClipboardBackend, ClipboardMarkers},
+    models::{
+        ClipboardEntry, ClipboardItem, RawItem, RawRepresentation, RichTextFormat, SourceApp,
+    },
+};
+use block2::RcBlock;
+use dispatch2::run_on_main;
+use gpui::{ImageFormat, http_client::Url};
+use objc2::{rc::Retained, runtime::ProtocolObject};
+use objc2_app_kit::{
+    NSBitmapImageFileType, NSBitmapImageRep, NSEvent, NSEventMask, NSEventModifierFlags, NSImage,
+    NSPasteboard, NSPasteboardItem, NSPasteboardType, NSPasteboardTypeFileURL,
+    NSPasteboardTypeHTML, NSPasteboardTypePNG, NSPasteboardTypeRTF, NSPasteboardTypeString,
+    NSPasteboardTypeTIFF, NSPasteboardTypeURL, NSWorkspace,
+};
+use objc2_foundation::{NSArray, NSData, NSDictionary, NSSize, NSString, NSURL};
+use std::{
+    collections::HashMap,
+    mem,
+    path::PathBuf,
+    ptr::NonNull,
+    sync::{Arc, Mutex, OnceLock},
+};
+
+const NSPASTEBOARD_TYPE_JPEG: &str = "public.jpeg";
+const NSPASTEBOARD_TYPE_GIF: &str = "com.compuserve.gif";
+
+/// Converts an icon to png bytes at 48x48
+fn icon_to_png(ns_image: &NSImage) -> Option<Vec<u8>> {
+    let target_size = NSSize::new(48.0, 48.0);
+    unsafe { ns_image.setSize(target_size) };
+
+    if let Some(tiff_data) = unsafe { ns_image.TIFFRepresentation() } {
+        if let Some(bitmap) = unsafe { NSBitmapImageRep::imageRepWithData(&tiff_data) } {
+            if let Some(png_data) = unsafe {
+                bitmap.representationUsingType_properties(
+                    NSBitmapImageFileType::PNG,
+                    &NSDictionary::new(),
+                )
+            } {
+                Some(png_data.to_vec())
+            } else {
+                None
+            }
+        } else {
+            None
+        }
+    } else {
+        None
+    }
+}
+
+fn get_file_icon(path: PathBuf) -> Option<Vec<u8>> {
+    run_on_main(move |_mtm| {
+        let workspace = unsafe { NSWorkspace::sharedWorkspace() };
+        let ns_image =
+            unsafe { workspace.iconForFile(&NSString::from_str(path.to_str().unwrap())) };
+        icon_to_png(&ns_image)
+    })
+}
+
+fn get_pasteboard_change_count() -> isize {
+    run_on_main(|_mtm| unsafe { NSPasteboard::generalPasteboard().changeCount() })
+}
+
+fn get_pasteboard_markers() -> ClipboardMarkers {
+    run_on_main(|_mtm| {
+        let types = unsafe { NSPasteboard::generalPasteboard().types() };
+        let types = types
+            .map(|types| types.iter().map(|t| t.to_string()).collect::<Vec<_>>())
+            .unwrap_or_default();
+        ClipboardMarkers::from_types(types.iter().map(String::as_str))
+    })
+}
+
+/// Icons are looked up once per app, converting them is too slow to repeat on every copy
+fn get_frontmost_app(icon_cache: &Mutex<HashMap<String, Option<Vec<u8>>>>) -> Option<SourceApp> {
+    run_on_main(|_mtm| {
+        let workspace = unsafe { NSWorkspace::sharedWorkspace() };
+        let app = unsafe { workspace.frontmostApplication() }?;
+        let bundle_id = unsafe { app.bundleIdentifier() }?.to_string();
+        let name = unsafe { app.localizedName() }.map(|name| name.to_string());
+        let icon_bytes = icon_cache
+            .lock()
+            .unwrap()
+            .entry(bundle_id.clone())
+            .or_insert_with(|| unsafe { app.icon() }.and_then(|icon| icon_to_png(&icon)))
+            .clone();
+
+        Some(SourceApp {
+            bundle_id,
+            name,
+            icon_bytes,
+        })
+    })
+}
+
+fn rich_text_type(format: RichTextFormat) -> &'static NSPasteboardType {
+    match format {
+        RichTextFormat::Rtf => unsafe { NSPasteboardTypeRTF },
+        RichTextFormat::Html => unsafe { NSPasteboardTypeHTML },
+    }
+}
+
+/// Formatted text on a pasteboard item along with its plain text, which replaces the text item
+fn get_rich_text(item: &NSPasteboardItem) -> Option<ClipboardItem> {
+    let text = unsafe { item.stringForType(NSPasteboardTypeString) }?.to_string();
+    RichTextFormat::ALL.into_iter().find_map(|format| {
+        let ns_data = unsafe { item.dataForType(rich_text_type(format)) }?;
+        Some(ClipboardItem::RichText {
+            text: text.clone(),
+            data: ns_data.to_vec(),
+            format,
+        })
+    })
+}
+
+fn get_pasteboard_items() -> Option<Vec<ClipboardItem>> {
+    run_on_main(|_mtm| {
+        let items = unsafe { NSPasteboard::generalPasteboard().pasteboardItems() };
+
+        if let Some(items) = items {
+            let collected = items
+                .iter()
+                .flat_map(|item| {
+                    let rich_text = get_rich_text(&item);
+                    // finder also puts each file's name on its item as text, which would only duplicate the file
+                    let skip_text = rich_text.is_some()
+                        || unsafe { item.stringForType(NSPasteboardTypeFileURL) }.is_some();
+                    rich_text.into_iter().chain(
+                        unsafe { item.types() }
+                            .iter()
+                            .filter_map(move |t| {
+                                let t: &NSPasteboardType = t.as_ref();
+                                let t_string = t.to_string();
+                                if unsafe { t == NSPasteboardTypeString } {
+                                    if skip_text {
+                                        None
+                                    } else if let Some(ns_string) =
+                                        unsafe { item.stringForType(NSPasteboardTypeString) }
+                                    {
+                                        Some(ClipboardItem::Text(ns_string.to_string()))
+                                    } else {
+                                        None
+                                    }
+                                } else if t == unsafe { NSPasteboardTypePNG } {
+                                    if let Some(ns_data) =
+                                        unsafe { item.dataForType(NSPasteboardTypePNG) }
+                                    {
+                                        Some(ClipboardItem::image(
+                                            ns_data.to_vec(),
+                                            ImageFormat::Png,
+                                        ))
+                                    } else {
+                                        None
+                                    }
+                                } else if t == unsafe { NSPasteboardTypeTIFF } {
+                                    if let Some(ns_data) =
+                                        unsafe { item.dataForType(NSPasteboardTypeTIFF) }
+                                    {
+                                        Some(ClipboardItem::image(
+                                            ns_data.to_vec(),
+                                            ImageFormat::Tiff,
+                                        ))
+                                    } else {
+                                        None
+                                    }
+                                } else if t == unsafe { NSPasteboardTypeURL } {
+                                    if let Some(ns_string) =
+                                        unsafe { item.stringForType(NSPasteboardTypeURL) }
+                                    {
+                                        match Url::parse(&ns_string.to_string()) {
+                                            Ok(url) => Some(ClipboardItem::Url(url)),
+                                            Err(_) => None,
+                                        }
+                                    } else {
+                                        None
+                                    }
+                                } else if t == unsafe { NSPasteboardTypeFileURL } {
+                                    if let Some(ns_string) =
+                                        unsafe { item.stringForType(NSPasteboardTypeFileURL) }
+                                    {
+                                        if let Some(url) =
+                                            unsafe { NSURL::URLWithString(&ns_string) }
+                                        {
+                                            if let Some(path) = unsafe { url.path() } {
+                                                let path_buf = PathBuf::from(path.to_string());
+                                                Some(ClipboardItem::File {
+                                                    path: path_buf.clone(),
+                                                    icon_bytes: get_file_icon(path_buf),
+                                                })
+                                            } else {
+                                                None
+                                            }
+                                        } else {
+                                            None
+                                        }
+                                    } else {
+                                        None
+                                    }
+                                } else if t_string == NSPASTEBOARD_TYPE_JPEG {
+                                    if let Some(ns_data) = unsafe {
+                                        item.dataForType(
+                                            NSString::from_str(NSPASTEBOARD_TYPE_JPEG).as_ref(),
+                                        )
+                                    } {
+                                        Some(ClipboardItem::image(
+                                            ns_data.to_vec(),
+                                            ImageFormat::Jpeg,
+                                        ))
+                                    } else {
+                                        None
+                                    }
+                                } else if t_string == NSPASTEBOARD_TYPE_GIF {
+                                    if let Some(ns_data) = unsafe {
+                                        item.dataForType(
+                                            NSString::from_str(NSPASTEBOARD_TYPE_GIF).as_ref(),
+                                        )
+                                    } {
+                                        Some(ClipboardItem::image(
+                                            ns_data.to_vec(),
+                                            ImageFormat::Gif,
+                                        ))
+                                    } else {
+                                        None
+                                    }
+                                } else {
+                                    None
+                                }
+                            })
+                            .collect::<Vec<_>>(),
+                    )
+                })
+                .collect();
+            Some(collected)
+        } else {
+            None
+        }
+    })
+}
+
+/// Reads every type of every pasteboard item, including ones `get_pasteboard_items` doesn't understand
+fn get_pasteboard_raw() -> Vec<RawItem> {
+    run_on_main(|_mtm| {
+        let Some(items) = (unsafe { NSPasteboard::generalPasteboard().pasteboardItems() }) else {
+            return Vec::new();
+        };
+        items
+            .iter()
+            .map(|item| RawItem {
+                representations: unsafe { item.types() }
+                    .iter()
+                    .filter_map(|t| {
+                        let data = unsafe { item.dataForType(&t) }?;
+                        Some(RawRepresentation {
+                            kind: t.to_string(),
+                            data: data.to_vec().into(),
+                        })
+                    })
+                    .collect(),
+            })
+            .collect()
+    })
+}
+
+/// Builds pasteboard items holding raw items exactly as they were read
+fn raw_pasteboard_items(raw: &[RawItem]) -> Vec<Retained<NSPasteboardItem>> {
+    raw.iter()
+        .map(|raw| {
+            let item = unsafe { NSPasteboardItem::new() };
+            for representation in &raw.representations {
+                let data = match representation.data.load() {
+                    Ok(data) => NSData::with_bytes(&data),
+                    Err(err) => {
+                        eprintln!("failed to read {}: {err}", representation.kind);
+                        continue;
+                    }
+                };
+                let t = NSString::from_str(&representation.kind);
+                unsafe { item.setData_forType(&data, &t) };
+            }
+            item
+        })
+        .collect()
+}
+
+/// Builds pasteboard items for typed items. Every file gets a pasteboard item of its own as they
+/// all use the file url type, everything else shares the first one.
+fn typed_pasteboard_items(items: &[ClipboardItem]) -> Vec<Retained<NSPasteboardItem>> {
+    let contents = unsafe { NSPasteboardItem::new() };
+    let mut has_contents = false;
+    let mut files = Vec::new();
+    items.iter().for_each(|item| match item {
+        ClipboardItem::Text(text) => {
+            let nsstring = NSString::from_str(text);
+            has_contents |=
+                unsafe { contents.setString_forType(&nsstring, NSPasteboardTypeString) };
+        }
+        ClipboardItem::RichText { text, data, format } => {
+            let nsdata = NSData::with_bytes(data);
+            has_contents |= unsafe { contents.setData_forType(&nsdata, rich_text_type(*format)) };
+            let nsstring = NSString::from_str(text);
+            has_contents |=
+                unsafe { contents.setString_forType(&nsstring, NSPasteboardTypeString) };
+        }
+        ClipboardItem::Url(url) => {
+            let nsstring = NSString::from_str(url.as_str());
+            has_contents |= unsafe { contents.setString_forType(&nsstring, NSPasteboardTypeURL) };
+        }
+        ClipboardItem::Image { bytes, format, .. } => {
+            let nsdata = match bytes.load() {
+                Ok(bytes) => NSData::with_bytes(&bytes),
+                Err(err) => {
+                    eprintln!("failed to read image: {err}");
+                    return;
+                }
+            };
+            let ns_jpeg = NSString::from_str(NSPASTEBOARD_TYPE_JPEG);
+            let ns_gif = NSString::from_str(NSPASTEBOARD_TYPE_GIF);
+            let nsimagetype = match format {
+                ImageFormat::Png => Some(unsafe { NSPasteboardTypePNG }),
+                ImageFormat::Tiff => Some(unsafe { NSPasteboardTypeTIFF }),
+                ImageFormat::Jpeg => Some(ns_jpeg.as_ref()),
+                ImageFormat::Gif => Some(ns_gif.as_ref()),
+                _ => None,
+            };
+            if let Some(nsimagetype) = nsimagetype {
+                has_contents |= unsafe { contents.setData_forType(&nsdata, nsimagetype) };
+            }
+        }
+        ClipboardItem::File { path, .. } => {
+            let url = match url::Url::from_file_path(path) {
+                Ok(url) => url,
+                Err(_) => return,
+            };
+            let file = unsafe { NSPasteboardItem::new() };
+            let nsstring = NSString::from_str(url.as_str());
+            unsafe { file.setString_forType(&nsstring, NSPasteboardTypeFileURL) };
+            files.push(file);
+        }
+    });
+    has_contents
+        .then_some(contents)
+        .into_iter()
+        .chain(files)
+        .collect()
+}
+
+fn copy_entry_to_clipboard(entry: &ClipboardEntry) {
+    let items = entry.items.clone();
+    let raw = entry.raw.clone();
+
+    run_on_main(move |_mtm| {
+        let pasteboard = unsafe { NSPasteboard::generalPasteboard() };
+        unsafe { pasteboard.clearContents() };
+        let items = if raw.is_empty() {
+            typed_pasteboard_items(&items)
+        } else {
+            raw_pasteboard_items(&raw)
+        };
+        let objects = items
+            .into_iter()
+            .map(ProtocolObject::from_retained)
+            .collect::<Vec<_>>();
+        unsafe { pasteboard.writeObjects(&NSArray::from_retained_slice(&objects)) };
+    })
+}
+
+/// Bumps `pastes` whenever ⌘V is pressed in another app, there's no way to see the pasteboard
+/// being read. The key is matched by the character it types rather than its key code, which
+/// differs between layouts such as Dvorak. Pastes from a menu aren't seen. Global key monitors
+/// need the accessibility permission, without it nothing is seen.
+fn watch_pastes(pastes: Arc<ChangeSignal>) {
+    run_on_main(move |_mtm| {
+        let handler = RcBlock::new(move |event: NonNull<NSEvent>| {
+            let event = unsafe { event.as_ref() };
+            let command = unsafe { event.modifierFlags() }.contains(NSEventModifierFlags::Command);
+            if command
+                && unsafe { event.charactersIgnoringModifiers() }
+                    .is_some_and(|characters| characters.to_string().eq_ignore_ascii_case("v"))
+            {
+                pastes.notify();
+            }
+        });
+        let monitor = unsafe {
+            NSEvent::addGlobalMonitorForEventsMatchingMask_handler(NSEventMask::KeyDown, &handler)
+        };
+        // watched for as long as the app runs
+        mem::forget(monitor);
+    })
+}
+
+/// Backend for the macos general pasteboard
+#[derive(Default)]
+pub struct MacosBackend {
+    app_icons: Mutex<HashMap<String, Option<Vec<u8>>>>,
+    /// Set up the first time it's asked for
+    pastes: OnceLock<Arc<ChangeSignal>>,
+}
+
+impl ClipboardBackend for MacosBackend {
+    fn change_count(&self) -> isize {
+        get_pasteboard_change_count()
+    }
+
+    fn paste_signal(&self) -> Option<Arc<ChangeSignal>> {
+        let pastes = self.pastes.get_or_init(|| {
+            let pastes = Arc::new(ChangeSignal::default());
+            watch_pastes(pastes.clone());
+            pastes
+        });
+        Some(pastes.clone())
+    }
+
+    fn read_items(&self) -> Option<Vec<ClipboardItem>> {
+        get_pasteboard_items()
+    }
+
+    fn read_raw(&self) -> Vec<RawItem> {
+        get_pasteboard_raw()
+    }
+
+    fn read_markers(&self) -> ClipboardMarkers {
+        get_pasteboard_markers()
+    }
+
+    fn frontmost_app(&self) -> Option<SourceApp> {
+        get_frontmost_app(&self.app_icons)
+    }
+
+    fn write_entry(&self, entry: &ClipboardEntry) {
+        copy_entry_to_clipboard(entry)
+    }
+}
@@ -0,0 +1,87 @@
+use std::sync::{Arc, Mutex};
+
+use crate::{
+    backend::{ChangeSignal, ChangeSource, ClipboardBackend, ClipboardMarkers},
+    models::{ClipboardEntry, ClipboardItem, RawItem},
+};
+
+/// In-memory clipboard, used where no system clipboard is available and to drive the monitor in tests
+pub struct MemoryBackend {
+    state: Mutex<State>,
+    changes: Arc<ChangeSignal>,
+}
+
+struct State {
+    items: Vec<ClipboardItem>,
+    raw: Vec<RawItem>,
+    markers: ClipboardMarkers,
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(State {
+                items: Vec::new(),
+                raw: Vec::new(),
+                markers: ClipboardMarkers::default(),
+            }),
+            changes: Arc::new(ChangeSignal::default()),
+        }
+    }
+
+    /// Simulates a copy made by another application, `raw` holds anything it offers beyond `items`
+    pub fn set_contents(
+        &self,
+        items: Vec<ClipboardItem>,
+        raw: Vec<RawItem>,
+        markers: ClipboardMarkers,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        state.items = items;
+        state.raw = raw;
+        state.markers = markers;
+        drop(state);
+        self.changes.notify();
+    }
+
+    /// The raw items currently on the clipboard
+    pub fn raw(&self) -> Vec<RawItem> {
+        self.state.lock().unwrap().raw.clone()
+    }
+}
+
+impl Default for MemoryBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ClipboardBackend for MemoryBackend {
+    fn change_count(&self) -> isize {
+        self.changes.count()
+    }
+
+    fn change_source(&self) -> ChangeSource {
+        ChangeSource::Push(self.changes.clone())
+    }
+
+    fn read_items(&self) -> Option<Vec<ClipboardItem>> {
+        Some(self.state.lock().unwrap().items.clone())
+    }
+
+    fn read_raw(&self) -> Vec<RawItem> {
+        self.raw()
+    }
+
+    fn read_markers(&self) -> ClipboardMarkers {
+        self.state.lock().unwrap().markers
+    }
+
+    fn write_entry(&self, entry: &ClipboardEntry) {
+        self.set_contents(
+            entry.items.clone(),
+            entry.raw.clone(),
+            ClipboardMarkers::default(),
+        );
+    }
+}
@@ -0,0 +1,273 @@
+//! Mapping between clipboard items and the mime types offered on the X11 and Wayland clipboards
+
+use std::sync::Arc;
+
+use gpui::{ImageFormat, http_client::Url};
+
+use crate::{
+    backend::ClipboardMarkers,
+    models::{ClipboardEntry, ClipboardItem, RawItem, RawRepresentation, RichTextFormat},
+};
+
+#[cfg(test)]
+mod tests;
+
+pub const UTF8_STRING: &str = "UTF8_STRING";
+pub const TEXT_PLAIN_UTF8: &str = "text/plain;charset=utf-8";
+pub const TEXT_PLAIN: &str = "text/plain";
+pub const TEXT: &str = "TEXT";
+/// Latin-1 text
+pub const STRING: &str = "STRING";
+pub const URI_LIST: &str = "text/uri-list";
+/// Files copied in GNOME Files and other file managers, which paste them as files rather than paths
+pub const GNOME_COPIED_FILES: &str = "x-special/gnome-copied-files";
+/// KeePassXC and other password managers offer this with "secret" for passwords
+pub const PASSWORD_MANAGER_HINT: &str = "x-kde-passwordManagerHint";
+
+/// Text types read from the clipboard, most preferred first
+const TEXT_TYPES: [&str; 4] = [UTF8_STRING, TEXT_PLAIN_UTF8, TEXT_PLAIN, STRING];
+
+/// X11 targets that ask the owner to do something rather than hand over data, left out of raw reads
+const META_TYPES: [&str; 7] = [
+    "TARGETS",
+    "MULTIPLE",
+    "TIMESTAMP",
+    "SAVE_TARGETS",
+    "DELETE",
+    "INSERT_SELECTION",
+    "INSERT_PROPERTY",
+];
+
+/// Image formats read from the clipboard, most preferred first
+const IMAGE_FORMATS: [ImageFormat; 6] = [
+    ImageFormat::Png,
+    ImageFormat::Jpeg,
+    ImageFormat::Gif,
+    ImageFormat::Webp,
+    ImageFormat::Bmp,
+    ImageFormat::Tiff,
+];
+
+/// Every mime type this module reads or offers
+pub fn known_types() -> impl Iterator<Item = &'static str> {
+    [
+        UTF8_STRING,
+        TEXT_PLAIN_UTF8,
+        TEXT_PLAIN,
+        TEXT,
+        STRING,
+        URI_LIST,
+        GNOME_COPIED_FILES,
+        PASSWORD_MANAGER_HINT,
+    ]
+    .into_iter()
+    .chain(
+        RichTextFormat::ALL
+            .into_iter()
+            .map(RichTextFormat::mime_type),
+    )
+    .chain(IMAGE_FORMATS.into_iter().map(ImageFormat::mime_type))
+}
+
+/// Reads the items on a clipboard offering `mime_types`.
+/// `read` fetches the data for a type, `None` if the owner refused to give it.
+pub fn read_items<E>(
+    mime_types: &[&str],
+    mut read: impl FnMut(&'static str) -> Result<Option<Vec<u8>>, E>,
+) -> Result<Vec<ClipboardItem>, E> {
+    let mut items = Vec::new();
+
+    if mime_types.contains(&URI_LIST)
+        && let Some(data) = read(URI_LIST)?
+    {
+        items.extend(parse_uri_list(&String::from_utf8_lossy(&data)));
+    }
+    // file managers also offer the paths as text, which would only duplicate the files
+    if items.is_empty()
+        && let Some(mime_type) = TEXT_TYPES
+            .into_iter()
+            .find(|mime_type| mime_types.contains(mime_type))
+        && let Some(data) = read(mime_type)?
+    {
+        let text = if mime_type == STRING {
+            data.iter().map(|&byte| byte as char).collect()
+        } else {
+            String::from_utf8_lossy(&data).into_owned()
+        };
+        // formatted text is only kept alongside plain text, which is what's shown and searched
+        let rich_text = match RichTextFormat::ALL
+            .into_iter()
+            .find(|format| mime_types.contains(&format.mime_type()))
+        {
+            Some(format) => read(format.mime_type())?.map(|data| (data, format)),
+            None => None,
+        };
+        items.push(match rich_text {
+            Some((data, format)) => ClipboardItem::RichText { text, data, format },
+            None => ClipboardItem::Text(text),
+        });
+    }
+    if let Some(format) = IMAGE_FORMATS
+        .into_iter()
+        .find(|format| mime_types.contains(&format.mime_type()))
+        && let Some(bytes) = read(format.mime_type())?
+    {
+        items.push(ClipboardItem::image(bytes, format));
+    }
+    Ok(items)
+}
+
+pub fn read_markers<E>(
+    mime_types: &[&str],
+    mut read: impl FnMut(&'static str) -> Result<Option<Vec<u8>>, E>,
+) -> Result<ClipboardMarkers, E> {
+    let concealed = mime_types.contains(&PASSWORD_MANAGER_HINT)
+        && read(PASSWORD_MANAGER_HINT)?.as_deref() == Some(b"secret");
+    Ok(ClipboardMarkers {
+        concealed,
+        ..ClipboardMarkers::default()
+    })
+}
+
+/// Reads every type on a clipboard offering `types` as one raw item, these clipboards never hold
+/// more. Types the owner refuses are left out.
+pub fn read_raw<E>(
+    types: &[&str],
+    mut read: impl FnMut(&str) -> Result<Option<Vec<u8>>, E>,
+) -> Result<Vec<RawItem>, E> {
+    let mut representations = Vec::new();
+    for &kind in types {
+        if META_TYPES.contains(&kind) {
+            continue;
+        }
+        if let Some(data) = read(kind)? {
+            representations.push(RawRepresentation {
+                kind: kind.to_string(),
+                data: data.into(),
+            });
+        }
+    }
+    Ok(if representations.is_empty() {
+        Vec::new()
+    } else {
+        vec![RawItem { representations }]
+    })
+}
+
+/// The data offered for each type when `entry` is copied, its raw items exactly as they were
+/// read where there are any
+pub fn entry_contents(entry: &ClipboardEntry) -> Vec<(String, Arc<Vec<u8>>)> {
+    if entry.raw.is_empty() {
+        return contents_for(&entry.items)
+            .into_iter()
+            .map(|(mime_type, data)| (mime_type.to_string(), data))
+            .collect();
+    }
+    let mut contents: Vec<(String, Arc<Vec<u8>>)> = Vec::new();
+    for representation in entry.raw.iter().flat_map(|raw| &raw.representations) {
+        if contents
+            .iter()
+            .any(|(kind, _)| *kind == representation.kind)
+        {
+            continue;
+        }
+        match representation.data.load() {
+            Ok(data) => contents.push((representation.kind.clone(), data)),
+            Err(err) => eprintln!("failed to read {}: {err}", representation.kind),
+        }
+    }
+    contents
+}
+
+/// Converts entry items into the data offered for each mime type
+pub fn contents_for(items: &[ClipboardItem]) -> Vec<(&'static str, Arc<Vec<u8>>)> {
+    let mut texts = Vec::new();
+    let mut fallback_texts = Vec::new();
+    let mut uris = Vec::new();
+    let mut files = Vec::new();
+    let mut images = Vec::new();
+    let mut rich_texts = Vec::new();
+    for item in items {
+        match item {
+            ClipboardItem::Text(text) => texts.push(text.clone()),
+            ClipboardItem::RichText { text, data, format } => {
+                texts.push(text.clone());
+                // documents can't be joined like plain text, the first of each format wins
+                let mime_type = format.mime_type();
+                if !rich_texts
+                    .iter()
+                    .any(|(existing, _)| *existing == mime_type)
+                {
+                    rich_texts.push((mime_type, Arc::new(data.clone())));
+                }
+            }
+            ClipboardItem::Url(url) => {
+                uris.push(url.to_string());
+                fallback_texts.push(url.to_string());
+            }
+            ClipboardItem::File { path, .. } => {
+                if let Ok(url) = Url::from_file_path(path) {
+                    uris.push(url.to_string());
+                    files.push(url.to_string());
+                }
+                fallback_texts.push(path.display().to_string());
+            }
+            ClipboardItem::Image { bytes, format, .. } => {
+                let mime_type = format.mime_type();
+                if IMAGE_FORMATS.contains(format)
+                    && !images.iter().any(|(existing, _)| *existing == mime_type)
+                {
+                    match bytes.load() {
+                        Ok(bytes) => images.push((mime_type, bytes)),
+                        Err(err) => eprintln!("failed to read image: {err}"),
+                    }
+                }
+            }
+        }
+    }
+
+    let mut contents = Vec::new();
+    if !uris.is_empty() {
+        contents.push((URI_LIST, Arc::new(uris.join("\r\n").into_bytes())));
+    }
+    if !files.is_empty() {
+        let copied = format!("copy\n{}", files.join("\n"));
+        contents.push((GNOME_COPIED_FILES, Arc::new(copied.into_bytes())));
+    }
+    contents.extend(images);
+    contents.extend(rich_texts);
+    if texts.is_empty() {
+        texts = fallback_texts;
+    }
+    if !texts.is_empty() {
+        let text = texts.join("\n");
+        let mut mime_types = vec![UTF8_STRING, TEXT_PLAIN_UTF8, TEXT, TEXT_PLAIN];
+        // STRING is latin-1, so it's only offered when the text reads the same either way
+        if text.is_ascii() {
+            mime_types.push(STRING);
+        }
+        let text = Arc::new(text.into_bytes());
+        contents.extend(
+            mime_types
+                .into_iter()
+                .map(|mime_type| (mime_type, text.clone())),
+        );
+    }
+    contents
+}
+
+/// Parses a `text/uri-list`, local files become file items and everything else urls
+fn parse_uri_list(list: &str) -> Vec<ClipboardItem> {
+    list.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| Url::parse(line).ok())
+        .map(|url| match url.to_file_path() {
+            Ok(path) if url.scheme() == "file" => ClipboardItem::File {
+                path,
+                icon_bytes: None,
+            },
+            _ => ClipboardItem::Url(url),
+        })
+        .collect()
+}
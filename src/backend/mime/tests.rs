@@ -0,0 +1,166 @@
+use std::{convert::Infallible, path::PathBuf};
+
+use gpui::{ImageFormat, http_client::Url};
+
+use crate::{
+    backend::mime::{
+        self, GNOME_COPIED_FILES, PASSWORD_MANAGER_HINT, STRING, TEXT_PLAIN_UTF8, URI_LIST,
+    },
+    models::{ClipboardEntry, ClipboardItem, RawItem, RawRepresentation, RichTextFormat},
+};
+
+/// Reads items back from what `contents_for` offers, like another app pasting them
+fn roundtrip(items: &[ClipboardItem]) -> Vec<ClipboardItem> {
+    let contents = mime::contents_for(items);
+    let mime_types = contents
+        .iter()
+        .map(|(mime_type, _)| *mime_type)
+        .collect::<Vec<_>>();
+    mime::read_items::<Infallible>(&mime_types, |mime_type| {
+        Ok(contents
+            .iter()
+            .find(|(offered, _)| *offered == mime_type)
+            .map(|(_, data)| data.to_vec()))
+    })
+    .unwrap()
+}
+
+fn offered(items: &[ClipboardItem], mime_type: &str) -> Option<String> {
+    mime::contents_for(items)
+        .into_iter()
+        .find(|(offered, _)| *offered == mime_type)
+        .map(|(_, data)| String::from_utf8(data.to_vec()).unwrap())
+}
+
+#[test]
+fn roundtrips_items() {
+    let items = [
+        vec![ClipboardItem::Text("héllo".to_string())],
+        vec![
+            ClipboardItem::File {
+                path: PathBuf::from("/tmp/some file.txt"),
+                icon_bytes: None,
+            },
+            ClipboardItem::Url(Url::parse("https://example.com/a").unwrap()),
+        ],
+        vec![
+            ClipboardItem::Text("caption".to_string()),
+            ClipboardItem::image(vec![1, 2, 3], ImageFormat::Png),
+        ],
+        vec![ClipboardItem::RichText {
+            text: "bold".to_string(),
+            data: b"<b>bold</b>".to_vec(),
+            format: RichTextFormat::Html,
+        }],
+    ];
+    for items in items {
+        assert!(roundtrip(&items) == items);
+    }
+}
+
+#[test]
+fn offers_files_for_file_managers() {
+    let items = [ClipboardItem::File {
+        path: PathBuf::from("/tmp/a b"),
+        icon_bytes: None,
+    }];
+    assert_eq!(
+        offered(&items, GNOME_COPIED_FILES).as_deref(),
+        Some("copy\nfile:///tmp/a%20b")
+    );
+    assert_eq!(
+        offered(&items, URI_LIST).as_deref(),
+        Some("file:///tmp/a%20b")
+    );
+    assert_eq!(offered(&items, "UTF8_STRING").as_deref(), Some("/tmp/a b"));
+}
+
+#[test]
+fn only_offers_latin1_for_ascii() {
+    let ascii = [ClipboardItem::Text("plain".to_string())];
+    let accented = [ClipboardItem::Text("café".to_string())];
+    assert!(offered(&ascii, STRING).is_some());
+    assert!(offered(&accented, STRING).is_none());
+}
+
+#[test]
+fn reads_latin1_and_password_hints() {
+    let read = |mime_type: &str| -> Result<Option<Vec<u8>>, Infallible> {
+        Ok(match mime_type {
+            STRING => Some(vec![b'c', b'a', b'f', 0xe9]),
+            PASSWORD_MANAGER_HINT => Some(b"secret".to_vec()),
+            _ => None,
+        })
+    };
+    let items = mime::read_items(&[STRING], read).unwrap();
+    assert!(items == [ClipboardItem::Text("café".to_string())]);
+
+    assert!(
+        mime::read_markers(&[STRING, PASSWORD_MANAGER_HINT], read)
+            .unwrap()
+            .concealed
+    );
+    assert!(!mime::read_markers(&[STRING], read).unwrap().concealed);
+}
+
+#[test]
+fn reads_every_offered_type_as_one_raw_item() {
+    let read = |mime_type: &str| -> Result<Option<Vec<u8>>, Infallible> {
+        Ok(match mime_type {
+            "TARGETS" => panic!("TARGETS isn't data"),
+            "text/html" => Some(b"<b>hi</b>".to_vec()),
+            "application/x-app-private" => Some(vec![1, 2, 3]),
+            _ => None,
+        })
+    };
+    let raw = mime::read_raw(
+        &[
+            "TARGETS",
+            "text/html",
+            "application/x-refused",
+            "application/x-app-private",
+        ],
+        read,
+    )
+    .unwrap();
+    assert!(
+        raw == [RawItem {
+            representations: vec![
+                RawRepresentation {
+                    kind: "text/html".to_string(),
+                    data: b"<b>hi</b>".to_vec().into(),
+                },
+                RawRepresentation {
+                    kind: "application/x-app-private".to_string(),
+                    data: vec![1, 2, 3].into(),
+                },
+            ],
+        }]
+    );
+    assert!(
+        mime::read_raw(&["application/x-refused"], read)
+            .unwrap()
+            .is_empty()
+    );
+}
+
+#[test]
+fn offers_raw_data_instead_of_the_items() {
+    let mut entry = ClipboardEntry::new(vec![ClipboardItem::Text("hi".to_string())]);
+    assert!(
+        mime::entry_contents(&entry)
+            .iter()
+            .any(|(mime_type, _)| mime_type == TEXT_PLAIN_UTF8)
+    );
+
+    entry.raw = vec![RawItem {
+        representations: vec![RawRepresentation {
+            kind: "application/x-app-private".to_string(),
+            data: vec![1, 2, 3].into(),
+        }],
+    }];
+    let contents = mime::entry_contents(&entry);
+    assert_eq!(contents.len(), 1);
+    assert_eq!(contents[0].0, "application/x-app-private");
+    assert_eq!(*contents[0].1, [1, 2, 3]);
+}
@@ -0,0 +1,117 @@
+//! Cases every system clipboard backend has to pass, run by the backends' own test modules
+//! against a real display server. Those tests are ignored by default since they need one, and
+//! all use the same clipboard, so they take turns through `SELECTION` rather than replacing
+//! each other's copies.
+
+use std::{
+    path::PathBuf,
+    sync::{Mutex, MutexGuard, PoisonError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use gpui::{ImageFormat, http_client::Url};
+
+use crate::{
+    backend::ClipboardBackend,
+    models::{ClipboardEntry, ClipboardItem, RawItem, RawRepresentation},
+};
+
+static SELECTION: Mutex<()> = Mutex::new(());
+
+/// Waits for the other tests to be done with the clipboard
+pub(super) fn take_turn() -> MutexGuard<'static, ()> {
+    SELECTION.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Waits up to two seconds for `done`, changes arrive on the backends' own threads
+pub(super) fn wait_until(what: &str, done: impl Fn() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while !done() {
+        assert!(Instant::now() < deadline, "{what} not noticed");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+pub(super) fn text(text: &str) -> Vec<ClipboardItem> {
+    vec![ClipboardItem::Text(text.to_string())]
+}
+
+/// Copies `items` from one client and reads them back from another
+fn roundtrip<B: ClipboardBackend>(
+    connect: impl Fn() -> B,
+    items: Vec<ClipboardItem>,
+) -> Vec<ClipboardItem> {
+    let _turn = take_turn();
+    let (copier, reader) = (connect(), connect());
+    let change_count = reader.change_count();
+    copier.write_entry(&ClipboardEntry::new(items));
+    wait_until("clipboard change", || reader.change_count() != change_count);
+    reader.read_items().unwrap()
+}
+
+pub(super) fn roundtrips_text<B: ClipboardBackend>(connect: impl Fn() -> B) {
+    let items = text("héllo clipboard");
+    assert!(roundtrip(connect, items.clone()) == items);
+}
+
+pub(super) fn roundtrips_files_and_urls<B: ClipboardBackend>(connect: impl Fn() -> B) {
+    let items = vec![
+        ClipboardItem::File {
+            path: PathBuf::from("/tmp/some file.txt"),
+            icon_bytes: None,
+        },
+        ClipboardItem::Url(Url::parse("https://example.com/a").unwrap()),
+    ];
+    assert!(roundtrip(connect, items.clone()) == items);
+}
+
+/// Well past what fits in one x11 request or a pipe's buffer, so it's only complete if the
+/// transfer is split up or reading and writing overlap
+pub(super) fn roundtrips_large_images<B: ClipboardBackend>(connect: impl Fn() -> B) {
+    let items = vec![ClipboardItem::image(
+        (0..8 << 20).map(|i| i as u8).collect(),
+        ImageFormat::Png,
+    )];
+    assert!(roundtrip(connect, items.clone()) == items);
+}
+
+/// Reading back a copy needs the backend to keep serving it while it's also the reader
+pub(super) fn counts_changes_and_keeps_serving_own_copies<B: ClipboardBackend>(
+    connect: impl Fn() -> B,
+) {
+    let _turn = take_turn();
+    let backend = connect();
+    for copied in ["first", "second"] {
+        let change_count = backend.change_count();
+        backend.write_entry(&ClipboardEntry::new(text(copied)));
+        wait_until("clipboard change", || {
+            backend.change_count() != change_count
+        });
+        assert!(backend.read_items().unwrap() == text(copied));
+    }
+}
+
+/// Every type the app offered is put back, including ones items can't hold
+pub(super) fn roundtrips_raw_data<B: ClipboardBackend>(connect: impl Fn() -> B) {
+    let _turn = take_turn();
+    let (copier, reader) = (connect(), connect());
+    let mut entry = ClipboardEntry::new(text("hi"));
+    entry.raw = vec![RawItem {
+        representations: vec![
+            RawRepresentation {
+                kind: "text/plain;charset=utf-8".to_string(),
+                data: b"hi".to_vec().into(),
+            },
+            RawRepresentation {
+                kind: "application/x-stele-test".to_string(),
+                data: vec![0, 1, 2, 255].into(),
+            },
+        ],
+    }];
+    let change_count = reader.change_count();
+    copier.write_entry(&entry);
+    wait_until("clipboard change", || reader.change_count() != change_count);
+    assert!(reader.read_items().unwrap() == text("hi"));
+    assert!(reader.read_raw() == entry.raw);
+}
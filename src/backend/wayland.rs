@@ -0,0 +1,441 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{self, PipeReader, Read, Write},
+    os::fd::{AsFd, AsRawFd},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use wayland_client::{
+    ConnectError, Connection, Dispatch, DispatchError, Proxy, QueueHandle, delegate_noop,
+    event_created_child,
+    globals::{BindError, GlobalError, GlobalListContents, registry_queue_init},
+    protocol::{wl_registry::WlRegistry, wl_seat::WlSeat},
+};
+
+use crate::{
+    backend::{ChangeSignal, ChangeSource, ClipboardBackend, ClipboardMarkers, mime},
+    models::{ClipboardEntry, ClipboardItem, RawItem},
+};
+
+use protocol::{
+    zwlr_data_control_device_v1::{self, ZwlrDataControlDeviceV1},
+    zwlr_data_control_manager_v1::ZwlrDataControlManagerV1,
+    zwlr_data_control_offer_v1::{self, ZwlrDataControlOfferV1},
+    zwlr_data_control_source_v1::{self, ZwlrDataControlSourceV1},
+};
+
+mod protocol;
+#[cfg(test)]
+mod tests;
+
+#[derive(Debug)]
+pub enum WaylandError {
+    Connect(ConnectError),
+    Globals(GlobalError),
+    /// The compositor doesn't offer wlr-data-control or a seat
+    Unsupported(BindError),
+    Dispatch(DispatchError),
+    Connection(wayland_client::backend::WaylandError),
+    Io(io::Error),
+    /// The selection owner didn't send its data in time
+    Timeout,
+}
+
+impl fmt::Display for WaylandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WaylandError::Connect(err) => write!(f, "failed to connect to the compositor: {err}"),
+            WaylandError::Globals(err) => write!(f, "failed to list compositor globals: {err}"),
+            WaylandError::Unsupported(err) => {
+                write!(f, "the compositor doesn't support wlr-data-control: {err}")
+            }
+            WaylandError::Dispatch(err) => write!(f, "failed to handle wayland events: {err}"),
+            WaylandError::Connection(err) => write!(f, "wayland connection failed: {err}"),
+            WaylandError::Io(err) => write!(f, "io error: {err}"),
+            WaylandError::Timeout => write!(f, "the clipboard owner didn't respond"),
+        }
+    }
+}
+
+impl std::error::Error for WaylandError {}
+
+impl From<ConnectError> for WaylandError {
+    fn from(err: ConnectError) -> Self {
+        WaylandError::Connect(err)
+    }
+}
+
+impl From<GlobalError> for WaylandError {
+    fn from(err: GlobalError) -> Self {
+        WaylandError::Globals(err)
+    }
+}
+
+impl From<BindError> for WaylandError {
+    fn from(err: BindError) -> Self {
+        WaylandError::Unsupported(err)
+    }
+}
+
+impl From<DispatchError> for WaylandError {
+    fn from(err: DispatchError) -> Self {
+        WaylandError::Dispatch(err)
+    }
+}
+
+impl From<wayland_client::backend::WaylandError> for WaylandError {
+    fn from(err: wayland_client::backend::WaylandError) -> Self {
+        WaylandError::Connection(err)
+    }
+}
+
+impl From<io::Error> for WaylandError {
+    fn from(err: io::Error) -> Self {
+        WaylandError::Io(err)
+    }
+}
+
+/// Clipboard of a wlroots based compositor such as sway, through wlr-data-control.
+/// The protocol is meant for clipboard managers, so offers arrive without a focused window, and
+/// copies made from the panel are served by stele until something else is copied.
+pub struct WaylandBackend {
+    conn: Connection,
+    queue: QueueHandle<State>,
+    manager: ZwlrDataControlManagerV1,
+    device: ZwlrDataControlDeviceV1,
+    shared: Arc<Shared>,
+}
+
+/// Selection state, updated as the compositor announces new offers
+struct Shared {
+    changes: Arc<ChangeSignal>,
+    /// Bumped whenever our contents are sent to another client
+    pastes: Arc<ChangeSignal>,
+    /// Reads of our own in progress, the sends they cause aren't pastes
+    receiving: AtomicUsize,
+    offers: Mutex<Offers>,
+}
+
+#[derive(Default)]
+struct Offers {
+    clipboard: Option<ZwlrDataControlOfferV1>,
+    primary: Option<ZwlrDataControlOfferV1>,
+    /// Whether the primary selection changed last
+    primary_changed: bool,
+}
+
+/// Mime types of an offer, announced right after the offer itself
+type OfferedTypes = Mutex<Vec<String>>;
+
+/// Data served for each mime type while a source is the selection
+struct SourceContents(Vec<(String, Arc<Vec<u8>>)>);
+
+struct State {
+    shared: Arc<Shared>,
+    primary: bool,
+}
+
+impl WaylandBackend {
+    /// Longest the selection owner may go without sending anything
+    const TIMEOUT: Duration = Duration::from_secs(1);
+    /// Longest a single read may take, however steadily the owner keeps sending
+    const READ_LIMIT: Duration = Duration::from_secs(5);
+
+    /// Connects to the compositor in `$WAYLAND_DISPLAY`, `primary` also records text selected
+    /// with the mouse
+    pub fn connect(primary: bool) -> Result<Self, WaylandError> {
+        let conn = Connection::connect_to_env()?;
+        let (globals, mut queue) = registry_queue_init::<State>(&conn)?;
+        let qh = queue.handle();
+        let seat = globals.bind::<WlSeat, _, _>(&qh, 1..=1, ())?;
+        // primary selection events need version 2
+        let manager = globals.bind::<ZwlrDataControlManagerV1, _, _>(&qh, 1..=2, ())?;
+        let device = manager.get_data_device(&seat, &qh, ());
+
+        let shared = Arc::new(Shared {
+            changes: Arc::new(ChangeSignal::default()),
+            pastes: Arc::new(ChangeSignal::default()),
+            receiving: AtomicUsize::new(0),
+            offers: Mutex::new(Offers::default()),
+        });
+        let mut state = State {
+            shared: shared.clone(),
+            primary,
+        };
+        // the compositor sends the current selection right away
+        queue.roundtrip(&mut state)?;
+        thread::spawn(move || {
+            loop {
+                if let Err(err) = queue.blocking_dispatch(&mut state) {
+                    eprintln!("lost the connection to the compositor: {err}");
+                    return;
+                }
+            }
+        });
+
+        Ok(Self {
+            conn,
+            queue: qh,
+            manager,
+            device,
+            shared,
+        })
+    }
+
+    /// The offer for the selection that changed last, with its mime types
+    fn current_offer(&self) -> Option<(ZwlrDataControlOfferV1, Vec<String>)> {
+        let offers = self.shared.offers.lock().unwrap();
+        let offer = if offers.primary_changed {
+            offers.primary.clone()
+        } else {
+            offers.clipboard.clone()
+        }?;
+        let mime_types = offer.data::<OfferedTypes>()?.lock().unwrap().clone();
+        Some((offer, mime_types))
+    }
+
+    fn receive(
+        &self,
+        offer: &ZwlrDataControlOfferV1,
+        mime_type: &str,
+    ) -> Result<Option<Vec<u8>>, WaylandError> {
+        // counted so reading contents we serve ourselves isn't taken for a paste
+        self.shared.receiving.fetch_add(1, Ordering::SeqCst);
+        let received = self.receive_from_owner(offer, mime_type);
+        self.shared.receiving.fetch_sub(1, Ordering::SeqCst);
+        received.map(Some)
+    }
+
+    fn receive_from_owner(
+        &self,
+        offer: &ZwlrDataControlOfferV1,
+        mime_type: &str,
+    ) -> Result<Vec<u8>, WaylandError> {
+        let (mut reader, writer) = io::pipe()?;
+        offer.receive(mime_type.to_string(), writer.as_fd());
+        self.conn.flush()?;
+        // the owner gets its own copy of the write end, ours has to be closed to see the end
+        drop(writer);
+        read_to_end(&mut reader, Self::TIMEOUT, Self::READ_LIMIT)
+    }
+}
+
+/// Reads until the writer closes the pipe, giving up if nothing arrives for `timeout` or the
+/// read as a whole takes longer than `limit`
+fn read_to_end(
+    reader: &mut PipeReader,
+    timeout: Duration,
+    limit: Duration,
+) -> Result<Vec<u8>, WaylandError> {
+    let deadline = Instant::now() + limit;
+    let mut data = Vec::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(WaylandError::Timeout);
+        }
+        let mut poll_fd = libc::pollfd {
+            fd: reader.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let wait = timeout.min(remaining);
+        match unsafe { libc::poll(&mut poll_fd, 1, wait.as_millis() as i32) } {
+            0 => return Err(WaylandError::Timeout),
+            -1 => {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err.into());
+                }
+            }
+            _ => match reader.read(&mut buf)? {
+                0 => return Ok(data),
+                read => data.extend_from_slice(&buf[..read]),
+            },
+        }
+    }
+}
+
+impl ClipboardBackend for WaylandBackend {
+    fn change_count(&self) -> isize {
+        self.shared.changes.count()
+    }
+
+    fn change_source(&self) -> ChangeSource {
+        ChangeSource::Push(self.shared.changes.clone())
+    }
+
+    fn paste_signal(&self) -> Option<Arc<ChangeSignal>> {
+        Some(self.shared.pastes.clone())
+    }
+
+    fn read_items(&self) -> Option<Vec<ClipboardItem>> {
+        let Some((offer, mime_types)) = self.current_offer() else {
+            return Some(Vec::new());
+        };
+        let mime_types = mime_types.iter().map(String::as_str).collect::<Vec<_>>();
+        mime::read_items(&mime_types, |mime_type| self.receive(&offer, mime_type))
+            .map_err(|err| eprintln!("failed to read the clipboard: {err}"))
+            .ok()
+    }
+
+    fn read_raw(&self) -> Vec<RawItem> {
+        let Some((offer, mime_types)) = self.current_offer() else {
+            return Vec::new();
+        };
+        let mime_types = mime_types.iter().map(String::as_str).collect::<Vec<_>>();
+        mime::read_raw(&mime_types, |mime_type| self.receive(&offer, mime_type))
+            .map_err(|err| eprintln!("failed to read the clipboard: {err}"))
+            .unwrap_or_default()
+    }
+
+    fn read_markers(&self) -> ClipboardMarkers {
+        let Some((offer, mime_types)) = self.current_offer() else {
+            return ClipboardMarkers::default();
+        };
+        let mime_types = mime_types.iter().map(String::as_str).collect::<Vec<_>>();
+        mime::read_markers(&mime_types, |mime_type| self.receive(&offer, mime_type))
+            .unwrap_or_default()
+    }
+
+    fn write_entry(&self, entry: &ClipboardEntry) {
+        let contents = mime::entry_contents(entry);
+        let mime_types = contents
+            .iter()
+            .map(|(mime_type, _)| mime_type.clone())
+            .collect::<Vec<_>>();
+        let source = self
+            .manager
+            .create_data_source(&self.queue, SourceContents(contents));
+        for mime_type in mime_types {
+            source.offer(mime_type);
+        }
+        self.device.set_selection(Some(&source));
+        if let Err(err) = self.conn.flush() {
+            eprintln!("failed to copy to the clipboard: {err}");
+        }
+    }
+}
+
+impl State {
+    fn set_offer(&mut self, offer: Option<ZwlrDataControlOfferV1>, primary: bool) {
+        if primary && !self.primary {
+            if let Some(offer) = offer {
+                offer.destroy();
+            }
+            return;
+        }
+
+        let mut offers = self.shared.offers.lock().unwrap();
+        let current = if primary {
+            &mut offers.primary
+        } else {
+            &mut offers.clipboard
+        };
+        if let Some(previous) = std::mem::replace(current, offer) {
+            previous.destroy();
+        }
+        offers.primary_changed = primary;
+        drop(offers);
+        self.shared.changes.notify();
+    }
+}
+
+impl Dispatch<WlRegistry, GlobalListContents> for State {
+    fn event(
+        _state: &mut Self,
+        _registry: &WlRegistry,
+        _event: <WlRegistry as Proxy>::Event,
+        _data: &GlobalListContents,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+delegate_noop!(State: ignore WlSeat);
+delegate_noop!(State: ZwlrDataControlManagerV1);
+
+impl Dispatch<ZwlrDataControlDeviceV1, ()> for State {
+    fn event(
+        state: &mut Self,
+        device: &ZwlrDataControlDeviceV1,
+        event: zwlr_data_control_device_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwlr_data_control_device_v1::Event::Selection { id } => state.set_offer(id, false),
+            zwlr_data_control_device_v1::Event::PrimarySelection { id } => {
+                state.set_offer(id, true)
+            }
+            zwlr_data_control_device_v1::Event::Finished => {
+                eprintln!("the compositor stopped sharing the clipboard");
+                device.destroy();
+            }
+            _ => {}
+        }
+    }
+
+    event_created_child!(State, ZwlrDataControlDeviceV1, [
+        zwlr_data_control_device_v1::EVT_DATA_OFFER_OPCODE => (ZwlrDataControlOfferV1, OfferedTypes::default()),
+    ]);
+}
+
+impl Dispatch<ZwlrDataControlOfferV1, OfferedTypes> for State {
+    fn event(
+        _state: &mut Self,
+        _offer: &ZwlrDataControlOfferV1,
+        event: zwlr_data_control_offer_v1::Event,
+        mime_types: &OfferedTypes,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let zwlr_data_control_offer_v1::Event::Offer { mime_type } = event;
+        mime_types.lock().unwrap().push(mime_type);
+    }
+}
+
+impl Dispatch<ZwlrDataControlSourceV1, SourceContents> for State {
+    fn event(
+        state: &mut Self,
+        source: &ZwlrDataControlSourceV1,
+        event: zwlr_data_control_source_v1::Event,
+        contents: &SourceContents,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwlr_data_control_source_v1::Event::Send { mime_type, fd } => {
+                let data = contents
+                    .0
+                    .iter()
+                    .find(|(offered, _)| *offered == mime_type)
+                    .map(|(_, data)| data.clone());
+                if data.is_some() && state.shared.receiving.load(Ordering::SeqCst) == 0 {
+                    state.shared.pastes.notify();
+                }
+                // written on its own thread so a slow reader doesn't hold up other events
+                thread::spawn(move || {
+                    let Some(data) = data else {
+                        return;
+                    };
+                    match File::from(fd).write_all(&data) {
+                        Ok(()) => {}
+                        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => {}
+                        Err(err) => eprintln!("failed to send clipboard contents: {err}"),
+                    }
+                });
+            }
+            zwlr_data_control_source_v1::Event::Cancelled => source.destroy(),
+        }
+    }
+}
@@ -0,0 +1,19 @@
+//! Client bindings for wlr-data-control, generated from the protocol description next to this file
+#![allow(
+    dead_code,
+    non_camel_case_types,
+    non_upper_case_globals,
+    non_snake_case
+)]
+#![allow(unused_imports, clippy::all)]
+
+use wayland_client;
+use wayland_client::protocol::*;
+
+pub mod __interfaces {
+    use wayland_client::protocol::__interfaces::*;
+    wayland_scanner::generate_interfaces!("src/backend/wayland/wlr-data-control-unstable-v1.xml");
+}
+use self::__interfaces::*;
+
+wayland_scanner::generate_client_code!("src/backend/wayland/wlr-data-control-unstable-v1.xml");
@@ -0,0 +1,118 @@
+//! The ignored tests need a wlroots compositor, run them inside a headless one with
+//! `WLR_BACKENDS=headless sway` and `cargo test wayland -- --ignored`. See `backend::tests` for
+//! the cases shared with x11.
+
+use std::{
+    env,
+    io::{self, Write},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    backend::{
+        ClipboardBackend, tests,
+        wayland::{WaylandBackend, WaylandError, read_to_end},
+    },
+    models::ClipboardEntry,
+};
+
+fn connect() -> WaylandBackend {
+    assert!(
+        env::var_os("WAYLAND_DISPLAY").is_some(),
+        "these tests need a wlroots compositor in $WAYLAND_DISPLAY"
+    );
+    WaylandBackend::connect(false).unwrap()
+}
+
+#[test]
+fn reads_until_the_owner_closes_the_pipe() {
+    let (mut reader, mut writer) = io::pipe().unwrap();
+    thread::spawn(move || writer.write_all(b"offered data"));
+    let data = read_to_end(&mut reader, Duration::from_secs(1), Duration::from_secs(5));
+    assert_eq!(data.unwrap(), b"offered data");
+}
+
+#[test]
+fn gives_up_on_owners_that_send_nothing() {
+    let (mut reader, writer) = io::pipe().unwrap();
+    let started = Instant::now();
+    let data = read_to_end(
+        &mut reader,
+        Duration::from_millis(50),
+        Duration::from_secs(5),
+    );
+    assert!(matches!(data, Err(WaylandError::Timeout)));
+    assert!(started.elapsed() < Duration::from_secs(1));
+    drop(writer);
+}
+
+#[test]
+fn gives_up_on_owners_that_never_stop_sending() {
+    let (mut reader, mut writer) = io::pipe().unwrap();
+    // a byte at a time, never pausing long enough to time out, until the read is abandoned
+    thread::spawn(move || {
+        while writer.write_all(b".").is_ok() {
+            thread::sleep(Duration::from_millis(5));
+        }
+    });
+    let started = Instant::now();
+    let data = read_to_end(
+        &mut reader,
+        Duration::from_millis(100),
+        Duration::from_millis(300),
+    );
+    assert!(matches!(data, Err(WaylandError::Timeout)));
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+#[ignore = "needs a wlroots compositor"]
+fn roundtrips_text() {
+    tests::roundtrips_text(connect);
+}
+
+#[test]
+#[ignore = "needs a wlroots compositor"]
+fn roundtrips_files_and_urls() {
+    tests::roundtrips_files_and_urls(connect);
+}
+
+#[test]
+#[ignore = "needs a wlroots compositor"]
+fn roundtrips_large_images() {
+    tests::roundtrips_large_images(connect);
+}
+
+#[test]
+#[ignore = "needs a wlroots compositor"]
+fn counts_changes_and_keeps_serving_own_copies() {
+    tests::counts_changes_and_keeps_serving_own_copies(connect);
+}
+
+#[test]
+#[ignore = "needs a wlroots compositor"]
+fn roundtrips_raw_data() {
+    tests::roundtrips_raw_data(connect);
+}
+
+#[test]
+#[ignore = "needs a wlroots compositor"]
+fn only_other_clients_reading_our_copies_are_pastes() {
+    let _turn = tests::take_turn();
+    let (backend, other) = (connect(), connect());
+    let pastes = backend.paste_signal().unwrap();
+    let (change_count, other_change_count) = (backend.change_count(), other.change_count());
+    backend.write_entry(&ClipboardEntry::new(tests::text("served")));
+    tests::wait_until("selection change", || {
+        backend.change_count() != change_count && other.change_count() != other_change_count
+    });
+
+    // the monitor reading back the copy it just made goes through `receiving`
+    let paste_count = pastes.count();
+    assert!(backend.read_items().unwrap() == tests::text("served"));
+    assert_eq!(pastes.count(), paste_count);
+
+    assert!(other.read_items().unwrap() == tests::text("served"));
+    tests::wait_until("paste", || pastes.count() != paste_count);
+}
@@ -0,0 +1,808 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, Cursor},
+    os::fd::AsRawFd,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU32, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use image::{RgbaImage, imageops::FilterType};
+use x11rb::{
+    COPY_DEPTH_FROM_PARENT, CURRENT_TIME, NONE,
+    connection::{Connection, RequestConnection},
+    errors::{ConnectError, ConnectionError, ReplyError, ReplyOrIdError},
+    protocol::{
+        Event,
+        xfixes::{ConnectionExt as _, SelectionEventMask},
+        xproto::{
+            Atom, AtomEnum, ChangeWindowAttributesAux, ConnectionExt as _, CreateWindowAux,
+            EventMask, PropMode, Property, SELECTION_NOTIFY_EVENT, SelectionNotifyEvent,
+            SelectionRequestEvent, Window, WindowClass,
+        },
+    },
+    rust_connection::RustConnection,
+    wrapper::ConnectionExt as _,
+};
+
+use crate::{
+    backend::{ChangeSignal, ChangeSource, ClipboardBackend, ClipboardMarkers, mime},
+    models::{ClipboardEntry, ClipboardItem, RawItem, SourceApp},
+};
+
+#[cfg(test)]
+mod tests;
+
+x11rb::atom_manager! {
+    Atoms: AtomsCookie {
+        CLIPBOARD,
+        TARGETS,
+        INCR,
+        STELE_SELECTION,
+        _NET_ACTIVE_WINDOW,
+        _NET_WM_ICON,
+    }
+}
+
+#[derive(Debug)]
+pub enum X11Error {
+    Connect(ConnectError),
+    Request(ReplyOrIdError),
+    /// Another client took the selection back before we could serve it
+    NotOwner,
+    /// The selection owner didn't answer in time
+    Timeout,
+    /// The selection owner sent more than we are willing to read
+    TooLarge,
+}
+
+impl fmt::Display for X11Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            X11Error::Connect(err) => write!(f, "failed to connect to the x server: {err}"),
+            X11Error::Request(err) => write!(f, "x11 request failed: {err}"),
+            X11Error::NotOwner => write!(f, "failed to take ownership of the clipboard"),
+            X11Error::Timeout => write!(f, "the clipboard owner didn't respond"),
+            X11Error::TooLarge => write!(f, "the clipboard contents are too large"),
+        }
+    }
+}
+
+impl std::error::Error for X11Error {}
+
+impl From<ConnectError> for X11Error {
+    fn from(err: ConnectError) -> Self {
+        X11Error::Connect(err)
+    }
+}
+
+impl From<ReplyOrIdError> for X11Error {
+    fn from(err: ReplyOrIdError) -> Self {
+        X11Error::Request(err)
+    }
+}
+
+impl From<ReplyError> for X11Error {
+    fn from(err: ReplyError) -> Self {
+        X11Error::Request(err.into())
+    }
+}
+
+impl From<ConnectionError> for X11Error {
+    fn from(err: ConnectionError) -> Self {
+        X11Error::Request(err.into())
+    }
+}
+
+/// An invisible window on its own connection, selections are requested and owned through windows
+struct Client {
+    conn: RustConnection,
+    window: Window,
+    root: Window,
+    atoms: Atoms,
+    /// Targets for the mime types items are read from and offered as
+    mime_types: Vec<(&'static str, Atom)>,
+}
+
+impl Client {
+    fn connect(event_mask: EventMask) -> Result<Self, X11Error> {
+        let (conn, screen_num) = x11rb::connect(None)?;
+        let root = conn.setup().roots[screen_num].root;
+        let window = conn.generate_id()?;
+        conn.create_window(
+            COPY_DEPTH_FROM_PARENT,
+            window,
+            root,
+            0,
+            0,
+            1,
+            1,
+            0,
+            WindowClass::INPUT_ONLY,
+            0,
+            &CreateWindowAux::new().event_mask(event_mask),
+        )?;
+        let atoms = Atoms::new(&conn)?.reply()?;
+        let cookies = mime::known_types()
+            .map(|mime_type| Ok((mime_type, conn.intern_atom(false, mime_type.as_bytes())?)))
+            .collect::<Result<Vec<_>, ConnectionError>>()?;
+        let mime_types = cookies
+            .into_iter()
+            .map(|(mime_type, cookie)| Ok((mime_type, cookie.reply()?.atom)))
+            .collect::<Result<Vec<_>, ReplyError>>()?;
+        conn.flush()?;
+        Ok(Self {
+            conn,
+            window,
+            root,
+            atoms,
+            mime_types,
+        })
+    }
+
+    fn target(&self, mime_type: &str) -> Option<Atom> {
+        self.mime_types
+            .iter()
+            .find(|(name, _)| *name == mime_type)
+            .map(|(_, target)| *target)
+    }
+
+    fn mime_type(&self, target: Atom) -> Option<&'static str> {
+        self.mime_types
+            .iter()
+            .find(|(_, atom)| *atom == target)
+            .map(|(name, _)| *name)
+    }
+
+    /// Pairs data offered by type name with the target of that name
+    fn targets_for(&self, contents: Vec<(String, Arc<Vec<u8>>)>) -> Result<Contents, X11Error> {
+        let cookies = contents
+            .iter()
+            .map(|(name, _)| self.conn.intern_atom(false, name.as_bytes()))
+            .collect::<Result<Vec<_>, _>>()?;
+        contents
+            .into_iter()
+            .zip(cookies)
+            .map(|((_, data), cookie)| Ok((cookie.reply()?.atom, data)))
+            .collect()
+    }
+
+    /// The names of `targets`, in the same order
+    fn target_names(&self, targets: &[Atom]) -> Result<Vec<String>, X11Error> {
+        let cookies = targets
+            .iter()
+            .map(|&target| self.conn.get_atom_name(target))
+            .collect::<Result<Vec<_>, _>>()?;
+        cookies
+            .into_iter()
+            .map(|cookie| Ok(String::from_utf8_lossy(&cookie.reply()?.name).into_owned()))
+            .collect()
+    }
+}
+
+/// Clipboard of an X11 display, the `CLIPBOARD` selection and optionally `PRIMARY`.
+/// X11 has no clipboard storage, so copies made from the panel are served by a background
+/// thread for as long as we own the selection.
+pub struct X11Backend {
+    reader: Mutex<Reader>,
+    owner: Arc<Owner>,
+}
+
+impl X11Backend {
+    /// Connects to the display in `$DISPLAY`, `primary` also records text selected with the mouse
+    pub fn connect(primary: bool) -> Result<Self, X11Error> {
+        let reader = Reader {
+            client: Client::connect(EventMask::PROPERTY_CHANGE)?,
+            app_icons: HashMap::new(),
+        };
+        let client = Client::connect(EventMask::NO_EVENT)?;
+        client.conn.xfixes_query_version(5, 0)?.reply()?;
+        let mut selections = vec![client.atoms.CLIPBOARD];
+        if primary {
+            selections.push(AtomEnum::PRIMARY.into());
+        }
+        for &selection in &selections {
+            client.conn.xfixes_select_selection_input(
+                client.window,
+                selection,
+                SelectionEventMask::SET_SELECTION_OWNER
+                    | SelectionEventMask::SELECTION_WINDOW_DESTROY
+                    | SelectionEventMask::SELECTION_CLIENT_CLOSE,
+            )?;
+        }
+        client.conn.flush()?;
+
+        let owner = Arc::new(Owner {
+            changed: AtomicU32::new(client.atoms.CLIPBOARD),
+            client,
+            reader_window: reader.client.window,
+            changes: Arc::new(ChangeSignal::default()),
+            pastes: Arc::new(ChangeSignal::default()),
+            contents: Mutex::new(Vec::new()),
+        });
+        thread::spawn({
+            let owner = owner.clone();
+            move || owner.run()
+        });
+        Ok(Self {
+            reader: Mutex::new(reader),
+            owner,
+        })
+    }
+
+    /// The selection that changed last
+    fn selection(&self) -> Atom {
+        self.owner.changed.load(Ordering::SeqCst)
+    }
+}
+
+impl ClipboardBackend for X11Backend {
+    fn change_count(&self) -> isize {
+        self.owner.changes.count()
+    }
+
+    fn change_source(&self) -> ChangeSource {
+        ChangeSource::Push(self.owner.changes.clone())
+    }
+
+    fn paste_signal(&self) -> Option<Arc<ChangeSignal>> {
+        Some(self.owner.pastes.clone())
+    }
+
+    fn read_items(&self) -> Option<Vec<ClipboardItem>> {
+        self.reader
+            .lock()
+            .unwrap()
+            .read_items(self.selection())
+            .map_err(|err| eprintln!("failed to read the clipboard: {err}"))
+            .ok()
+    }
+
+    fn read_raw(&self) -> Vec<RawItem> {
+        self.reader
+            .lock()
+            .unwrap()
+            .read_raw(self.selection())
+            .map_err(|err| eprintln!("failed to read the clipboard: {err}"))
+            .unwrap_or_default()
+    }
+
+    fn read_markers(&self) -> ClipboardMarkers {
+        self.reader
+            .lock()
+            .unwrap()
+            .read_markers(self.selection())
+            .unwrap_or_default()
+    }
+
+    fn frontmost_app(&self) -> Option<SourceApp> {
+        self.reader
+            .lock()
+            .unwrap()
+            .active_app()
+            .map_err(|err| eprintln!("failed to look up the active window: {err}"))
+            .ok()
+            .flatten()
+    }
+
+    fn write_entry(&self, entry: &ClipboardEntry) {
+        let contents = match self.owner.client.targets_for(mime::entry_contents(entry)) {
+            Ok(contents) => contents,
+            Err(err) => {
+                eprintln!("failed to copy to the clipboard: {err}");
+                return;
+            }
+        };
+        *self.owner.contents.lock().unwrap() = contents;
+        if let Err(err) = self.owner.take_clipboard() {
+            eprintln!("failed to copy to the clipboard: {err}");
+        }
+    }
+}
+
+/// Data served for each target while we own the clipboard
+type Contents = Vec<(Atom, Arc<Vec<u8>>)>;
+
+/// Owns the clipboard for copies made from stele and counts selection changes
+struct Owner {
+    client: Client,
+    /// Window our own reads are made through, they aren't pastes
+    reader_window: Window,
+    changes: Arc<ChangeSignal>,
+    /// Bumped for every conversion of our contents another client asks for
+    pastes: Arc<ChangeSignal>,
+    changed: AtomicU32,
+    contents: Mutex<Contents>,
+}
+
+/// Data too large for a single request, sent in chunks as the requestor deletes each one
+struct Transfer {
+    requestor: Window,
+    property: Atom,
+    target: Atom,
+    data: Arc<Vec<u8>>,
+    offset: usize,
+}
+
+impl Owner {
+    fn take_clipboard(&self) -> Result<(), X11Error> {
+        let Client {
+            conn,
+            window,
+            atoms,
+            ..
+        } = &self.client;
+        conn.set_selection_owner(*window, atoms.CLIPBOARD, CURRENT_TIME)?;
+        if conn.get_selection_owner(atoms.CLIPBOARD)?.reply()?.owner != *window {
+            return Err(X11Error::NotOwner);
+        }
+        Ok(())
+    }
+
+    /// Largest chunk sent in one request, larger data is sent incrementally
+    fn chunk_size(&self) -> usize {
+        (self.client.conn.maximum_request_bytes() / 4).min(1 << 20)
+    }
+
+    fn run(&self) {
+        let mut transfers = Vec::new();
+        loop {
+            let event = match self.client.conn.wait_for_event() {
+                Ok(event) => event,
+                Err(err) => {
+                    eprintln!("lost the connection to the x server: {err}");
+                    return;
+                }
+            };
+            if let Err(err) = self.handle(event, &mut transfers) {
+                eprintln!("failed to serve the clipboard: {err}");
+            }
+        }
+    }
+
+    fn handle(&self, event: Event, transfers: &mut Vec<Transfer>) -> Result<(), X11Error> {
+        let Client {
+            conn,
+            window,
+            atoms,
+            ..
+        } = &self.client;
+        match event {
+            Event::XfixesSelectionNotify(event) => {
+                self.changed.store(event.selection, Ordering::SeqCst);
+                self.changes.notify();
+            }
+            Event::SelectionRequest(request) => self.answer(request, transfers)?,
+            // a clear for an ownership we've since taken back can arrive late
+            Event::SelectionClear(event)
+                if event.selection == atoms.CLIPBOARD
+                    && conn.get_selection_owner(atoms.CLIPBOARD)?.reply()?.owner != *window =>
+            {
+                self.contents.lock().unwrap().clear();
+            }
+            Event::PropertyNotify(event) if event.state == Property::DELETE => {
+                if let Some(index) = transfers.iter().position(|transfer| {
+                    transfer.requestor == event.window && transfer.property == event.atom
+                }) {
+                    self.continue_transfer(transfers, index)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Sends the next chunk once the requestor deleted the previous one
+    fn continue_transfer(
+        &self,
+        transfers: &mut Vec<Transfer>,
+        index: usize,
+    ) -> Result<(), X11Error> {
+        let conn = &self.client.conn;
+        let transfer = &mut transfers[index];
+        let end = (transfer.offset + self.chunk_size()).min(transfer.data.len());
+        // an empty chunk marks the end of the transfer
+        conn.change_property8(
+            PropMode::REPLACE,
+            transfer.requestor,
+            transfer.property,
+            transfer.target,
+            &transfer.data[transfer.offset..end],
+        )?;
+        let finished = transfer.offset == end;
+        transfer.offset = end;
+        if finished {
+            let transfer = transfers.remove(index);
+            if !transfers
+                .iter()
+                .any(|other| other.requestor == transfer.requestor)
+            {
+                conn.change_window_attributes(
+                    transfer.requestor,
+                    &ChangeWindowAttributesAux::new().event_mask(EventMask::NO_EVENT),
+                )?;
+            }
+        }
+        conn.flush()?;
+        Ok(())
+    }
+
+    fn answer(
+        &self,
+        request: SelectionRequestEvent,
+        transfers: &mut Vec<Transfer>,
+    ) -> Result<(), X11Error> {
+        let Client { conn, atoms, .. } = &self.client;
+        // clients predating ICCCM 2 leave the property unset and expect the target to be used
+        let property = if request.property == NONE {
+            request.target
+        } else {
+            request.property
+        };
+
+        let contents = self.contents.lock().unwrap();
+        let served = if request.selection != atoms.CLIPBOARD || contents.is_empty() {
+            false
+        } else if request.target == atoms.TARGETS {
+            let targets = std::iter::once(atoms.TARGETS)
+                .chain(contents.iter().map(|(target, _)| *target))
+                .collect::<Vec<_>>();
+            conn.change_property32(
+                PropMode::REPLACE,
+                request.requestor,
+                property,
+                AtomEnum::ATOM,
+                &targets,
+            )?;
+            true
+        } else if let Some((_, data)) = contents
+            .iter()
+            .find(|(target, _)| *target == request.target)
+        {
+            if data.len() > self.chunk_size() {
+                conn.change_window_attributes(
+                    request.requestor,
+                    &ChangeWindowAttributesAux::new().event_mask(EventMask::PROPERTY_CHANGE),
+                )?;
+                conn.change_property32(
+                    PropMode::REPLACE,
+                    request.requestor,
+                    property,
+                    atoms.INCR,
+                    &[data.len() as u32],
+                )?;
+                transfers.push(Transfer {
+                    requestor: request.requestor,
+                    property,
+                    target: request.target,
+                    data: data.clone(),
+                    offset: 0,
+                });
+            } else {
+                conn.change_property8(
+                    PropMode::REPLACE,
+                    request.requestor,
+                    property,
+                    request.target,
+                    data,
+                )?;
+            }
+            true
+        } else {
+            false
+        };
+        drop(contents);
+        if served && request.target != atoms.TARGETS && request.requestor != self.reader_window {
+            self.pastes.notify();
+        }
+
+        conn.send_event(
+            false,
+            request.requestor,
+            EventMask::NO_EVENT,
+            SelectionNotifyEvent {
+                response_type: SELECTION_NOTIFY_EVENT,
+                sequence: 0,
+                time: request.time,
+                requestor: request.requestor,
+                selection: request.selection,
+                target: request.target,
+                property: if served { property } else { NONE },
+            },
+        )?;
+        conn.flush()?;
+        Ok(())
+    }
+}
+
+/// Reads selections by asking their owner to convert them into a property on our window
+struct Reader {
+    client: Client,
+    app_icons: HashMap<String, Option<Vec<u8>>>,
+}
+
+impl Reader {
+    /// Longest the selection owner may go without answering
+    const TIMEOUT: Duration = Duration::from_secs(1);
+    /// Longest an incremental transfer may take, however steadily the owner keeps sending
+    const READ_LIMIT: Duration = Duration::from_secs(5);
+    /// Most an incremental transfer may send before it's abandoned
+    const SIZE_LIMIT: usize = 256 << 20;
+    const ICON_SIZE: u32 = 48;
+
+    fn read_items(&self, selection: Atom) -> Result<Vec<ClipboardItem>, X11Error> {
+        let mime_types = self.mime_types(selection)?;
+        mime::read_items(&mime_types, |mime_type| {
+            self.convert_to(selection, mime_type)
+        })
+    }
+
+    fn read_markers(&self, selection: Atom) -> Result<ClipboardMarkers, X11Error> {
+        let mime_types = self.mime_types(selection)?;
+        mime::read_markers(&mime_types, |mime_type| {
+            self.convert_to(selection, mime_type)
+        })
+    }
+
+    /// Every target of `selection` and its data
+    fn read_raw(&self, selection: Atom) -> Result<Vec<RawItem>, X11Error> {
+        let targets = self.targets(selection)?;
+        let names = self.client.target_names(&targets)?;
+        let types = names.iter().map(String::as_str).collect::<Vec<_>>();
+        mime::read_raw(&types, |name| {
+            let index = names.iter().position(|other| other == name);
+            match index {
+                Some(index) => self.convert(selection, targets[index]),
+                None => Ok(None),
+            }
+        })
+    }
+
+    /// The known mime types among the targets of `selection`
+    fn mime_types(&self, selection: Atom) -> Result<Vec<&'static str>, X11Error> {
+        Ok(self
+            .targets(selection)?
+            .into_iter()
+            .filter_map(|target| self.client.mime_type(target))
+            .collect())
+    }
+
+    fn convert_to(&self, selection: Atom, mime_type: &str) -> Result<Option<Vec<u8>>, X11Error> {
+        match self.client.target(mime_type) {
+            Some(target) => self.convert(selection, target),
+            None => Ok(None),
+        }
+    }
+
+    /// Targets the owner of `selection` can convert to, empty if nothing owns it
+    fn targets(&self, selection: Atom) -> Result<Vec<Atom>, X11Error> {
+        let Client { conn, atoms, .. } = &self.client;
+        if conn.get_selection_owner(selection)?.reply()?.owner == NONE {
+            return Ok(Vec::new());
+        }
+        let targets = self
+            .convert(selection, atoms.TARGETS)?
+            .map(|data| {
+                data.chunks_exact(4)
+                    .map(|atom| u32::from_ne_bytes([atom[0], atom[1], atom[2], atom[3]]))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        // some old clients don't answer TARGETS but still convert to text
+        if targets.is_empty() {
+            return Ok([mime::UTF8_STRING, mime::STRING]
+                .into_iter()
+                .filter_map(|mime_type| self.client.target(mime_type))
+                .collect());
+        }
+        Ok(targets)
+    }
+
+    /// Converts `selection` to `target`, `None` if the owner refused
+    fn convert(&self, selection: Atom, target: Atom) -> Result<Option<Vec<u8>>, X11Error> {
+        let Client {
+            conn,
+            window,
+            atoms,
+            ..
+        } = &self.client;
+        let property = atoms.STELE_SELECTION;
+        conn.delete_property(*window, property)?;
+        conn.convert_selection(*window, selection, target, property, CURRENT_TIME)?;
+        conn.flush()?;
+
+        let notify = self.wait_for(Instant::now() + Self::TIMEOUT, |event| match event {
+            Event::SelectionNotify(event)
+                if event.requestor == *window
+                    && event.selection == selection
+                    && event.target == target =>
+            {
+                Some(event)
+            }
+            _ => None,
+        })?;
+        if notify.property == NONE {
+            return Ok(None);
+        }
+
+        let Client {
+            conn,
+            window,
+            atoms,
+            ..
+        } = &self.client;
+        let reply = conn
+            .get_property(true, *window, property, AtomEnum::ANY, 0, u32::MAX)?
+            .reply()?;
+        if reply.type_ != atoms.INCR {
+            return Ok(Some(reply.value));
+        }
+
+        // deleting the INCR property asked the owner for the first chunk
+        let limit = Instant::now() + Self::READ_LIMIT;
+        let mut data = Vec::new();
+        loop {
+            let deadline = (Instant::now() + Self::TIMEOUT).min(limit);
+            self.wait_for(deadline, |event| match event {
+                Event::PropertyNotify(event)
+                    if event.atom == property && event.state == Property::NEW_VALUE =>
+                {
+                    Some(())
+                }
+                _ => None,
+            })?;
+            let chunk = self
+                .client
+                .conn
+                .get_property(
+                    true,
+                    self.client.window,
+                    property,
+                    AtomEnum::ANY,
+                    0,
+                    u32::MAX,
+                )?
+                .reply()?;
+            if chunk.value.is_empty() {
+                return Ok(Some(data));
+            }
+            if data.len() + chunk.value.len() > Self::SIZE_LIMIT {
+                return Err(X11Error::TooLarge);
+            }
+            data.extend_from_slice(&chunk.value);
+        }
+    }
+
+    /// Waits until `deadline` for the first event `matches` accepts, dropping the others
+    fn wait_for<T>(
+        &self,
+        deadline: Instant,
+        mut matches: impl FnMut(Event) -> Option<T>,
+    ) -> Result<T, X11Error> {
+        let conn = &self.client.conn;
+        loop {
+            while let Some(event) = conn.poll_for_event()? {
+                if let Some(found) = matches(event) {
+                    return Ok(found);
+                }
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(X11Error::Timeout);
+            }
+            // blocks until the server sends more, anything already read is queued and was handled
+            let mut poll_fd = libc::pollfd {
+                fd: conn.stream().as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            if unsafe { libc::poll(&mut poll_fd, 1, remaining.as_millis() as i32) } == -1 {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(ConnectionError::IoError(err).into());
+                }
+            }
+        }
+    }
+
+    /// The application of the active window, identified by its `WM_CLASS` class name
+    fn active_app(&mut self) -> Result<Option<SourceApp>, X11Error> {
+        let Client {
+            conn, root, atoms, ..
+        } = &self.client;
+        let active = conn
+            .get_property(
+                false,
+                *root,
+                atoms._NET_ACTIVE_WINDOW,
+                AtomEnum::WINDOW,
+                0,
+                1,
+            )?
+            .reply()?;
+        let Some(window) = active
+            .value32()
+            .and_then(|mut windows| windows.next())
+            .filter(|window| *window != NONE)
+        else {
+            return Ok(None);
+        };
+
+        // WM_CLASS holds the instance and class names, nul separated
+        let class = conn
+            .get_property(false, window, AtomEnum::WM_CLASS, AtomEnum::STRING, 0, 256)?
+            .reply()?;
+        let Some(class) = class
+            .value
+            .split(|&byte| byte == 0)
+            .nth(1)
+            .filter(|class| !class.is_empty())
+            .map(|class| String::from_utf8_lossy(class).into_owned())
+        else {
+            return Ok(None);
+        };
+
+        let icon_bytes = match self.app_icons.get(&class) {
+            Some(icon) => icon.clone(),
+            None => {
+                let icon = conn
+                    .get_property(
+                        false,
+                        window,
+                        atoms._NET_WM_ICON,
+                        AtomEnum::CARDINAL,
+                        0,
+                        u32::MAX,
+                    )?
+                    .reply()?
+                    .value32()
+                    .and_then(|data| icon_to_png(&data.collect::<Vec<_>>(), Self::ICON_SIZE));
+                self.app_icons.insert(class.clone(), icon.clone());
+                icon
+            }
+        };
+        Ok(Some(SourceApp {
+            bundle_id: class,
+            name: None,
+            icon_bytes,
+        }))
+    }
+}
+
+/// Converts the `_NET_WM_ICON` icon closest to `size` to png bytes at `size`x`size`.
+/// The property holds any number of icons, each a width, a height and then ARGB pixels.
+fn icon_to_png(data: &[u32], size: u32) -> Option<Vec<u8>> {
+    let mut icons = Vec::new();
+    let mut rest = data;
+    while let [width, height, pixels @ ..] = rest {
+        let len = (*width as usize).checked_mul(*height as usize)?;
+        if len == 0 || pixels.len() < len {
+            break;
+        }
+        icons.push((*width, *height, &pixels[..len]));
+        rest = &pixels[len..];
+    }
+    let (width, height, pixels) = icons
+        .iter()
+        .filter(|(width, ..)| *width >= size)
+        .min_by_key(|(width, ..)| *width)
+        .or_else(|| icons.iter().max_by_key(|(width, ..)| *width))?;
+
+    let rgba = pixels
+        .iter()
+        .flat_map(|pixel| {
+            let [a, r, g, b] = pixel.to_be_bytes();
+            [r, g, b, a]
+        })
+        .collect();
+    let icon = RgbaImage::from_raw(*width, *height, rgba)?;
+    let icon = image::imageops::resize(&icon, size, size, FilterType::Triangle);
+    let mut png = Vec::new();
+    icon.write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+        .ok()?;
+    Some(png)
+}
@@ -0,0 +1,181 @@
+//! Run with `xvfb-run cargo test x11 -- --ignored`, see `backend::tests` for the shared cases.
+
+use std::{
+    env, thread,
+    time::{Duration, Instant},
+};
+
+use x11rb::{
+    COPY_DEPTH_FROM_PARENT, CURRENT_TIME,
+    connection::Connection,
+    protocol::{
+        Event,
+        xproto::{
+            ChangeWindowAttributesAux, ConnectionExt as _, CreateWindowAux, EventMask, PropMode,
+            Property, SELECTION_NOTIFY_EVENT, SelectionNotifyEvent, WindowClass,
+        },
+    },
+    rust_connection::RustConnection,
+    wrapper::ConnectionExt as _,
+};
+
+use crate::backend::{ClipboardBackend, tests, x11::X11Backend};
+
+fn connect() -> X11Backend {
+    assert!(
+        env::var_os("DISPLAY").is_some(),
+        "these tests need an x server in $DISPLAY"
+    );
+    X11Backend::connect(false).unwrap()
+}
+
+#[test]
+#[ignore = "needs an x server"]
+fn roundtrips_text() {
+    tests::roundtrips_text(connect);
+}
+
+#[test]
+#[ignore = "needs an x server"]
+fn roundtrips_files_and_urls() {
+    tests::roundtrips_files_and_urls(connect);
+}
+
+#[test]
+#[ignore = "needs an x server"]
+fn roundtrips_large_images_incrementally() {
+    tests::roundtrips_large_images(connect);
+}
+
+#[test]
+#[ignore = "needs an x server"]
+fn counts_changes_and_keeps_serving_own_copies() {
+    tests::counts_changes_and_keeps_serving_own_copies(connect);
+}
+
+#[test]
+#[ignore = "needs an x server"]
+fn roundtrips_raw_data() {
+    tests::roundtrips_raw_data(connect);
+}
+
+/// Takes the clipboard from a window of its own connection, which doesn't serve anything yet
+fn fake_owner(backend: &X11Backend) -> RustConnection {
+    let (conn, screen_num) = x11rb::connect(None).unwrap();
+    let window = conn.generate_id().unwrap();
+    conn.create_window(
+        COPY_DEPTH_FROM_PARENT,
+        window,
+        conn.setup().roots[screen_num].root,
+        0,
+        0,
+        1,
+        1,
+        0,
+        WindowClass::INPUT_ONLY,
+        0,
+        &CreateWindowAux::new(),
+    )
+    .unwrap();
+    let clipboard = conn
+        .intern_atom(false, b"CLIPBOARD")
+        .unwrap()
+        .reply()
+        .unwrap();
+    let change_count = backend.change_count();
+    conn.set_selection_owner(window, clipboard.atom, CURRENT_TIME)
+        .unwrap();
+    conn.flush().unwrap();
+    tests::wait_until("clipboard change", || {
+        backend.change_count() != change_count
+    });
+    conn
+}
+
+#[test]
+#[ignore = "needs an x server"]
+fn gives_up_on_owners_that_never_answer() {
+    let _turn = tests::take_turn();
+    let backend = connect();
+    // owns the clipboard but never handles the requests it gets
+    let _owner = fake_owner(&backend);
+
+    let started = Instant::now();
+    assert!(backend.read_items().is_none());
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
+
+#[test]
+#[ignore = "needs an x server"]
+fn gives_up_on_owners_that_never_stop_sending() {
+    let _turn = tests::take_turn();
+    let backend = connect();
+
+    // answers everything incrementally, a byte at a time, never pausing long enough to time out
+    let owner = fake_owner(&backend);
+    let incr = owner.intern_atom(false, b"INCR").unwrap().reply().unwrap();
+    thread::spawn(move || {
+        let mut transfers = Vec::new();
+        while let Ok(event) = owner.wait_for_event() {
+            match event {
+                Event::SelectionRequest(request) => {
+                    owner
+                        .change_window_attributes(
+                            request.requestor,
+                            &ChangeWindowAttributesAux::new()
+                                .event_mask(EventMask::PROPERTY_CHANGE),
+                        )
+                        .unwrap();
+                    owner
+                        .change_property32(
+                            PropMode::REPLACE,
+                            request.requestor,
+                            request.property,
+                            incr.atom,
+                            &[u32::MAX],
+                        )
+                        .unwrap();
+                    owner
+                        .send_event(
+                            false,
+                            request.requestor,
+                            EventMask::NO_EVENT,
+                            SelectionNotifyEvent {
+                                response_type: SELECTION_NOTIFY_EVENT,
+                                sequence: 0,
+                                time: request.time,
+                                requestor: request.requestor,
+                                selection: request.selection,
+                                target: request.target,
+                                property: request.property,
+                            },
+                        )
+                        .unwrap();
+                    transfers.push((request.requestor, request.property, request.target));
+                }
+                Event::PropertyNotify(event) if event.state == Property::DELETE => {
+                    if let Some(&(requestor, property, target)) =
+                        transfers.iter().find(|(requestor, property, _)| {
+                            *requestor == event.window && *property == event.atom
+                        })
+                    {
+                        thread::sleep(Duration::from_millis(5));
+                        let _ = owner.change_property8(
+                            PropMode::REPLACE,
+                            requestor,
+                            property,
+                            target,
+                            b".",
+                        );
+                    }
+                }
+                _ => {}
+            }
+            let _ = owner.flush();
+        }
+    });
+
+    let started = Instant::now();
+    assert!(backend.read_items().is_none());
+    assert!(started.elapsed() < Duration::from_secs(8));
+}
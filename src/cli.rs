@@ -0,0 +1,233 @@
+use std::io::{self, IsTerminal, Write};
+
+use crate::{
+    ipc::{Client, IpcError, ListedEntry, Request, Response, socket_path},
+    models::{ClipboardEntry, ClipboardItem},
+};
+
+const USAGE: &str = "usage: stele [--json] <command>
+       stele --headless
+
+run without arguments to start stele, or with --headless to only capture history, without the
+panel, tray icon or hotkey, and control it with the commands below
+
+commands:
+  list             list history, newest first
+  get <n>          print the contents of entry n
+  copy <n>         copy entry n back to the clipboard
+  search <query>   fuzzy search history, best match first
+  pin <n>          pin entry n
+  unpin <n>        unpin entry n
+  delete <n>       remove entry n from history
+  clear [--all]    remove every unpinned entry, or every entry with --all
+  watch            print entries as they're added, promoted or removed
+  quit             shut the running instance down
+
+entries are numbered from 0, the most recent copy";
+
+const MAX_PREVIEW_CHARS: usize = 60;
+
+/// Runs a cli command against the running instance, returns the process exit code
+pub fn run(args: &[String]) -> i32 {
+    let json = args.iter().any(|arg| arg == "--json");
+    let args = args
+        .iter()
+        .map(String::as_str)
+        .filter(|arg| *arg != "--json")
+        .collect::<Vec<_>>();
+
+    if matches!(args.first(), Some(&"help" | &"-h" | &"--help")) {
+        println!("{USAGE}");
+        return 0;
+    }
+    let request = match parse(&args) {
+        Ok(request) => request,
+        Err(message) => {
+            eprintln!("{message}\n\n{USAGE}");
+            return 2;
+        }
+    };
+
+    let mut client = match Client::connect(&socket_path()) {
+        Ok(client) => client,
+        Err(IpcError::Io(err)) => {
+            eprintln!("failed to reach stele, is it running? ({err})");
+            return 1;
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            return 1;
+        }
+    };
+    let result = match request {
+        Request::Subscribe => watch(client, json),
+        request => match client.request(&request) {
+            Ok(response) if json => print_json(response),
+            Ok(response) => print_plain(response),
+            Err(err) => {
+                eprintln!("{err}");
+                return 1;
+            }
+        },
+    };
+    match result {
+        Ok(()) => 0,
+        // the reader went away, e.g. `stele list | head`
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => 0,
+        Err(err) => {
+            eprintln!("{err}");
+            1
+        }
+    }
+}
+
+fn parse(args: &[&str]) -> Result<Request, String> {
+    let index = || -> Result<usize, String> {
+        let arg = args
+            .get(1)
+            .ok_or_else(|| format!("`{}` needs an entry number", args[0]))?;
+        arg.parse()
+            .map_err(|_| format!("`{arg}` isn't a valid entry number"))
+    };
+
+    match args.first() {
+        Some(&"list") => Ok(Request::List),
+        Some(&"get") => Ok(Request::Get { index: index()? }),
+        Some(&"copy") => Ok(Request::Copy { index: index()? }),
+        Some(&"search") if args.len() > 1 => Ok(Request::Search {
+            query: args[1..].join(" "),
+        }),
+        Some(&"search") => Err("`search` needs a query".to_string()),
+        Some(&"pin") => Ok(Request::Pin {
+            index: index()?,
+            pinned: true,
+        }),
+        Some(&"unpin") => Ok(Request::Pin {
+            index: index()?,
+            pinned: false,
+        }),
+        Some(&"delete") => Ok(Request::Delete { index: index()? }),
+        Some(&"clear") => Ok(Request::Clear {
+            all: args.get(1) == Some(&"--all"),
+        }),
+        Some(&"watch") => Ok(Request::Subscribe),
+        Some(&"quit") => Ok(Request::Quit),
+        Some(command) => Err(format!("unknown command `{command}`")),
+        None => Err("missing command".to_string()),
+    }
+}
+
+fn print_json(response: Response) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    match response {
+        Response::Entries(entries) => serde_json::to_writer(&mut stdout, &entries)?,
+        Response::Entry(entry) => serde_json::to_writer(&mut stdout, &entry)?,
+        Response::Hello { .. } | Response::Done => return Ok(()),
+    }
+    writeln!(stdout)
+}
+
+fn print_plain(response: Response) -> io::Result<()> {
+    match response {
+        Response::Entries(entries) => {
+            let mut stdout = io::stdout().lock();
+            for entry in entries {
+                writeln!(stdout, "{}", summary(&entry))?;
+            }
+            Ok(())
+        }
+        Response::Entry(entry) => print_contents(&entry),
+        Response::Hello { .. } | Response::Done => Ok(()),
+    }
+}
+
+/// Prints history changes until the running instance shuts down
+fn watch(client: Client, json: bool) -> io::Result<()> {
+    let subscription = client.subscribe().map_err(io::Error::other)?;
+    for event in subscription {
+        let event = event.map_err(io::Error::other)?;
+        let mut stdout = io::stdout().lock();
+        if json {
+            let line = serde_json::json!({ "event": event.name(), "entry": event.entry() });
+            serde_json::to_writer(&mut stdout, &line)?;
+            writeln!(stdout)?;
+        } else {
+            writeln!(stdout, "{}\t{}", event.name(), preview(event.entry()))?;
+        }
+        stdout.flush()?;
+    }
+    Ok(())
+}
+
+/// One line describing an entry: its number, `*` if pinned and a preview of its items
+fn summary(listed: &ListedEntry) -> String {
+    let pin = if listed.entry.pinned { "* " } else { "" };
+    format!("{}\t{pin}{}", listed.index, preview(&listed.entry))
+}
+
+fn preview(entry: &ClipboardEntry) -> String {
+    entry
+        .items
+        .iter()
+        .map(item_preview)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn item_preview(item: &ClipboardItem) -> String {
+    let preview = match item {
+        ClipboardItem::Text(text) | ClipboardItem::RichText { text, .. } => {
+            text.lines().next().unwrap_or_default().to_string()
+        }
+        ClipboardItem::Url(url) => url.to_string(),
+        ClipboardItem::File { path, .. } => path.display().to_string(),
+        ClipboardItem::Image { format, .. } => format!("[{} image]", format.mime_type()),
+    };
+    if preview.chars().count() > MAX_PREVIEW_CHARS {
+        let truncated = preview.chars().take(MAX_PREVIEW_CHARS).collect::<String>();
+        format!("{truncated}...")
+    } else {
+        preview
+    }
+}
+
+/// Writes an entry's contents as they'd be pasted: text verbatim, urls and file paths one per line
+/// and images as raw bytes, so the output can be piped straight into other tools
+fn print_contents(listed: &ListedEntry) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    let is_terminal = stdout.is_terminal();
+    let mut ends_with_newline = true;
+    for (i, item) in listed.entry.items.iter().enumerate() {
+        if i > 0 && !ends_with_newline {
+            writeln!(stdout)?;
+        }
+        match item {
+            ClipboardItem::Text(text) | ClipboardItem::RichText { text, .. } => {
+                stdout.write_all(text.as_bytes())?;
+                ends_with_newline = text.ends_with('\n');
+            }
+            ClipboardItem::Url(url) => {
+                writeln!(stdout, "{url}")?;
+                ends_with_newline = true;
+            }
+            ClipboardItem::File { path, .. } => {
+                writeln!(stdout, "{}", path.display())?;
+                ends_with_newline = true;
+            }
+            ClipboardItem::Image { .. } if is_terminal => {
+                return Err(io::Error::other(format!(
+                    "entry {} is an image, redirect the output to a file to save it",
+                    listed.index
+                )));
+            }
+            ClipboardItem::Image { bytes, .. } => {
+                stdout.write_all(&bytes.load()?)?;
+                ends_with_newline = true;
+            }
+        }
+    }
+    if is_terminal && !ends_with_newline {
+        writeln!(stdout)?;
+    }
+    stdout.flush()
+}
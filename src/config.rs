@@ -0,0 +1,371 @@
+use std::{
+    env, fmt, fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use global_hotkey::hotkey::HotKey;
+use gpui::{App, AsyncApp, Global};
+use serde::Deserialize;
+
+use crate::backend::ClipboardMarkers;
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    /// Maximum number of entries kept in history
+    pub history_limit: usize,
+    /// Maximum bytes history may hold in memory, counting text, images and icons
+    pub history_byte_limit: usize,
+    /// Global shortcut that toggles the panel
+    pub hotkey: HotKey,
+    /// How often the clipboard is checked for changes, on platforms that don't announce them
+    pub poll_interval: Duration,
+    /// How often it's checked once nothing was copied for a while
+    pub idle_poll_interval: Duration,
+    /// Waits between attempts to read a copy whose data isn't there yet
+    pub settle_delays: Vec<Duration>,
+    /// Also record text selected with the mouse on X11, read once at startup
+    pub primary_selection: bool,
+    pub panel: PanelConfig,
+    pub sensitive: SensitiveConfig,
+    pub apps: AppsConfig,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PanelConfig {
+    pub width: f32,
+    pub height: f32,
+}
+
+/// Which applications copies are captured from, by bundle identifier
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AppsConfig {
+    /// Copies made in these apps are never captured
+    pub deny: Vec<String>,
+    /// When not empty, only copies made in these apps are captured
+    pub allow: Vec<String>,
+}
+
+impl AppsConfig {
+    /// Whether a copy made while `bundle_id` was frontmost should be captured
+    pub fn allows(&self, bundle_id: Option<&str>) -> bool {
+        match bundle_id {
+            Some(bundle_id) => {
+                !self.deny.iter().any(|denied| denied == bundle_id)
+                    && (self.allow.is_empty()
+                        || self.allow.iter().any(|allowed| allowed == bundle_id))
+            }
+            None => self.allow.is_empty(),
+        }
+    }
+}
+
+/// What to do with a copy that carries a clipboard marker, ordered from least to most restrictive
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MarkerPolicy {
+    /// Record it like any other copy
+    Keep,
+    /// Record it in memory only and drop it after `expire_after`
+    Expire,
+    /// Don't record it
+    Skip,
+}
+
+/// How copies marked by password managers and similar apps are handled
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SensitiveConfig {
+    pub concealed: MarkerPolicy,
+    pub transient: MarkerPolicy,
+    pub auto_generated: MarkerPolicy,
+    pub expire_after: Duration,
+}
+
+impl SensitiveConfig {
+    /// The most restrictive policy among the markers present
+    pub fn policy_for(&self, markers: ClipboardMarkers) -> MarkerPolicy {
+        [
+            (markers.concealed, self.concealed),
+            (markers.transient, self.transient),
+            (markers.auto_generated, self.auto_generated),
+        ]
+        .into_iter()
+        .filter_map(|(present, policy)| present.then_some(policy))
+        .max()
+        .unwrap_or(MarkerPolicy::Keep)
+    }
+}
+
+impl Global for Config {}
+
+impl Default for Config {
+    fn default() -> Self {
+        ConfigFile::default()
+            .validate()
+            .expect("default config is valid")
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Invalid {
+        field: &'static str,
+        message: String,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, err) => write!(f, "failed to read {}: {err}", path.display()),
+            ConfigError::Parse(path, err) => write!(f, "failed to parse {}: {err}", path.display()),
+            ConfigError::Invalid { field, message } => {
+                write!(f, "invalid value for `{field}`: {message}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// On-disk representation, every field is optional and falls back to its default
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    history_limit: usize,
+    history_size_mb: usize,
+    hotkey: String,
+    poll_interval_ms: u64,
+    idle_poll_interval_ms: u64,
+    settle_delays_ms: Vec<u64>,
+    primary_selection: bool,
+    panel: PanelFile,
+    sensitive: SensitiveFile,
+    apps: AppsConfig,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct PanelFile {
+    width: f32,
+    height: f32,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SensitiveFile {
+    concealed: MarkerPolicy,
+    transient: MarkerPolicy,
+    auto_generated: MarkerPolicy,
+    expire_after_secs: u64,
+}
+
+impl Default for ConfigFile {
+    fn default() -> Self {
+        Self {
+            history_limit: 20,
+            history_size_mb: 256,
+            hotkey: "cmd+shift+v".to_string(),
+            poll_interval_ms: 100,
+            idle_poll_interval_ms: 1_000,
+            settle_delays_ms: vec![10, 50, 100, 150, 200, 300, 400],
+            primary_selection: false,
+            panel: PanelFile::default(),
+            sensitive: SensitiveFile::default(),
+            apps: AppsConfig::default(),
+        }
+    }
+}
+
+impl Default for SensitiveFile {
+    fn default() -> Self {
+        Self {
+            concealed: MarkerPolicy::Skip,
+            transient: MarkerPolicy::Skip,
+            auto_generated: MarkerPolicy::Keep,
+            expire_after_secs: 60,
+        }
+    }
+}
+
+impl Default for PanelFile {
+    fn default() -> Self {
+        Self {
+            width: 220.0,
+            height: 250.0,
+        }
+    }
+}
+
+fn check_range<T: PartialOrd + Copy + fmt::Display>(
+    field: &'static str,
+    value: T,
+    min: T,
+    max: T,
+) -> Result<T, ConfigError> {
+    if !(min..=max).contains(&value) {
+        return Err(ConfigError::Invalid {
+            field,
+            message: format!("{value} is out of range, expected {min} to {max}"),
+        });
+    }
+    Ok(value)
+}
+
+fn check_settle_delays(delays_ms: &[u64]) -> Result<Vec<Duration>, ConfigError> {
+    if delays_ms.len() > 20 {
+        return Err(ConfigError::Invalid {
+            field: "settle_delays_ms",
+            message: format!("{} delays given, at most 20 are allowed", delays_ms.len()),
+        });
+    }
+    delays_ms
+        .iter()
+        .map(|&delay| check_range("settle_delays_ms", delay, 1, 5_000).map(Duration::from_millis))
+        .collect()
+}
+
+fn check_bundle_ids(apps: AppsConfig) -> Result<AppsConfig, ConfigError> {
+    for (field, ids) in [("apps.deny", &apps.deny), ("apps.allow", &apps.allow)] {
+        if ids.iter().any(|id| id.trim().is_empty()) {
+            return Err(ConfigError::Invalid {
+                field,
+                message: "bundle identifiers can't be empty".to_string(),
+            });
+        }
+    }
+    Ok(apps)
+}
+
+impl ConfigFile {
+    fn validate(self) -> Result<Config, ConfigError> {
+        let hotkey = self
+            .hotkey
+            .parse::<HotKey>()
+            .map_err(|err| ConfigError::Invalid {
+                field: "hotkey",
+                message: err.to_string(),
+            })?;
+
+        Ok(Config {
+            history_limit: check_range("history_limit", self.history_limit, 1, 100_000)?,
+            history_byte_limit: check_range("history_size_mb", self.history_size_mb, 1, 65_536)?
+                * 1024
+                * 1024,
+            hotkey,
+            poll_interval: Duration::from_millis(check_range(
+                "poll_interval_ms",
+                self.poll_interval_ms,
+                10,
+                5_000,
+            )?),
+            idle_poll_interval: Duration::from_millis(check_range(
+                "idle_poll_interval_ms",
+                self.idle_poll_interval_ms,
+                self.poll_interval_ms.max(10),
+                60_000,
+            )?),
+            settle_delays: check_settle_delays(&self.settle_delays_ms)?,
+            primary_selection: self.primary_selection,
+            panel: PanelConfig {
+                width: check_range("panel.width", self.panel.width, 100.0, 2_000.0)?,
+                height: check_range("panel.height", self.panel.height, 100.0, 2_000.0)?,
+            },
+            sensitive: SensitiveConfig {
+                concealed: self.sensitive.concealed,
+                transient: self.sensitive.transient,
+                auto_generated: self.sensitive.auto_generated,
+                expire_after: Duration::from_secs(check_range(
+                    "sensitive.expire_after_secs",
+                    self.sensitive.expire_after_secs,
+                    1,
+                    86_400,
+                )?),
+            },
+            apps: check_bundle_ids(self.apps)?,
+        })
+    }
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/stele/config.toml`, `~/.config/stele/config.toml` if unset
+    pub fn path() -> PathBuf {
+        env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+            .or_else(|| dirs::home_dir().map(|home| home.join(".config")))
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("stele")
+            .join("config.toml")
+    }
+
+    /// Loads the config file at `path`, a missing file gives the defaults
+    pub fn load_from(path: &Path) -> Result<Self, ConfigError> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(ConfigError::Io(path.to_path_buf(), err)),
+        };
+
+        toml::from_str::<ConfigFile>(&contents)
+            .map_err(|err| ConfigError::Parse(path.to_path_buf(), err))?
+            .validate()
+    }
+
+    pub fn load() -> Result<Self, ConfigError> {
+        Self::load_from(&Self::path())
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+pub struct ConfigWatcher {}
+
+impl ConfigWatcher {
+    const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+    /// Reloads the global `Config` whenever the file at `path` changes.
+    /// A file that fails to load is reported and the previous config stays in effect.
+    pub fn spawn(cx: &mut App, path: PathBuf) {
+        cx.spawn(move |cx: &mut AsyncApp| {
+            let cx = cx.clone();
+            async move {
+                let mut last_modified = modified_time(&path);
+                loop {
+                    cx.background_executor().timer(Self::POLL_INTERVAL).await;
+
+                    let modified = modified_time(&path);
+                    if modified == last_modified {
+                        continue;
+                    }
+                    last_modified = modified;
+
+                    match Config::load_from(&path) {
+                        Ok(config) => {
+                            let updated = cx.update(|cx| {
+                                if *cx.global::<Config>() != config {
+                                    cx.set_global(config);
+                                }
+                            });
+                            if updated.is_err() {
+                                break;
+                            }
+                        }
+                        Err(err) => eprintln!("{err}, keeping previous config"),
+                    }
+                }
+            }
+        })
+        .detach();
+    }
+}
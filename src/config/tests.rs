@@ -0,0 +1,222 @@
+use std::{env, fs, path::Path, process, time::Duration};
+
+use global_hotkey::hotkey::{Code, HotKey, Modifiers};
+
+use crate::{
+    backend::ClipboardMarkers,
+    config::{AppsConfig, Config, ConfigError, ConfigFile, MarkerPolicy, SensitiveConfig},
+};
+
+fn parse(toml: &str) -> Result<Config, ConfigError> {
+    toml::from_str::<ConfigFile>(toml).unwrap().validate()
+}
+
+/// The field a config was rejected for
+fn invalid_field(toml: &str) -> &'static str {
+    match parse(toml) {
+        Err(ConfigError::Invalid { field, .. }) => field,
+        Err(err) => panic!("expected an invalid value, got {err}"),
+        Ok(_) => panic!("expected `{toml}` to be rejected"),
+    }
+}
+
+#[test]
+fn empty_file_gives_the_defaults() {
+    let config = parse("").unwrap();
+    assert_eq!(config, Config::default());
+    assert_eq!(config.history_limit, 20);
+    assert_eq!(config.history_byte_limit, 256 * 1024 * 1024);
+    assert_eq!(
+        config.hotkey,
+        HotKey::new(Some(Modifiers::SUPER | Modifiers::SHIFT), Code::KeyV)
+    );
+    assert_eq!(config.poll_interval, Duration::from_millis(100));
+    assert_eq!(config.idle_poll_interval, Duration::from_secs(1));
+    assert_eq!(config.settle_delays.len(), 7);
+    assert!(!config.primary_selection);
+    assert_eq!((config.panel.width, config.panel.height), (220.0, 250.0));
+    assert_eq!(config.sensitive.concealed, MarkerPolicy::Skip);
+    assert_eq!(config.sensitive.transient, MarkerPolicy::Skip);
+    assert_eq!(config.sensitive.auto_generated, MarkerPolicy::Keep);
+    assert!(config.apps.deny.is_empty() && config.apps.allow.is_empty());
+}
+
+#[test]
+fn missing_file_gives_the_defaults() {
+    let path = Path::new("/nonexistent/stele/config.toml");
+    assert_eq!(Config::load_from(path).unwrap(), Config::default());
+}
+
+#[test]
+fn set_fields_override_the_defaults() {
+    let config = parse(
+        r#"
+        history_limit = 500
+        hotkey = "ctrl+alt+h"
+        settle_delays_ms = [5, 5]
+
+        [panel]
+        width = 400
+
+        [sensitive]
+        concealed = "expire"
+        expire_after_secs = 30
+
+        [apps]
+        deny = ["com.agilebits.onepassword7"]
+        "#,
+    )
+    .unwrap();
+    assert_eq!(config.history_limit, 500);
+    assert_eq!(
+        config.hotkey,
+        HotKey::new(Some(Modifiers::CONTROL | Modifiers::ALT), Code::KeyH)
+    );
+    assert_eq!(config.settle_delays, [Duration::from_millis(5); 2]);
+    assert_eq!((config.panel.width, config.panel.height), (400.0, 250.0));
+    assert_eq!(config.sensitive.concealed, MarkerPolicy::Expire);
+    assert_eq!(config.sensitive.expire_after, Duration::from_secs(30));
+    assert_eq!(config.apps.deny, ["com.agilebits.onepassword7"]);
+}
+
+#[test]
+fn out_of_range_values_are_rejected() {
+    let cases = [
+        ("history_limit = 0", "history_limit"),
+        ("history_limit = 100001", "history_limit"),
+        ("history_size_mb = 0", "history_size_mb"),
+        ("poll_interval_ms = 5", "poll_interval_ms"),
+        // idle polling can't be faster than regular polling
+        (
+            "poll_interval_ms = 500\nidle_poll_interval_ms = 200",
+            "idle_poll_interval_ms",
+        ),
+        ("settle_delays_ms = [0]", "settle_delays_ms"),
+        ("settle_delays_ms = [10, 6000]", "settle_delays_ms"),
+        ("[panel]\nwidth = 50", "panel.width"),
+        ("[panel]\nheight = 5000", "panel.height"),
+        (
+            "[sensitive]\nexpire_after_secs = 0",
+            "sensitive.expire_after_secs",
+        ),
+        ("[apps]\ndeny = [\" \"]", "apps.deny"),
+        ("[apps]\nallow = [\"\"]", "apps.allow"),
+    ];
+    for (toml, field) in cases {
+        assert_eq!(invalid_field(toml), field, "for `{toml}`");
+    }
+    let too_many_delays = format!("settle_delays_ms = {:?}", [10; 21]);
+    assert_eq!(invalid_field(&too_many_delays), "settle_delays_ms");
+}
+
+#[test]
+fn bad_hotkey_is_rejected() {
+    assert_eq!(invalid_field(r#"hotkey = "cmd+shift+nope""#), "hotkey");
+    assert_eq!(invalid_field(r#"hotkey = """#), "hotkey");
+}
+
+#[test]
+fn unknown_fields_and_bad_types_fail_to_parse() {
+    for toml in [
+        "history_limt = 10",
+        "[panel]\ndepth = 10",
+        "[sensitive]\nconcealed = \"forget\"",
+        "history_limit = \"ten\"",
+    ] {
+        assert!(
+            toml::from_str::<ConfigFile>(toml).is_err(),
+            "expected `{toml}` not to parse"
+        );
+    }
+
+    let path = env::temp_dir().join(format!("stele-config-{}.toml", process::id()));
+    fs::write(&path, "history_limt = 10").unwrap();
+    let result = Config::load_from(&path);
+    let _ = fs::remove_file(&path);
+    assert!(matches!(result, Err(ConfigError::Parse(..))));
+}
+
+#[test]
+fn most_restrictive_marker_policy_wins() {
+    use MarkerPolicy::*;
+
+    let config = SensitiveConfig {
+        concealed: Skip,
+        transient: Expire,
+        auto_generated: Keep,
+        expire_after: Duration::from_secs(60),
+    };
+    let markers = |concealed, transient, auto_generated| ClipboardMarkers {
+        concealed,
+        transient,
+        auto_generated,
+    };
+    let cases = [
+        (markers(false, false, false), Keep),
+        (markers(true, false, false), Skip),
+        (markers(false, true, false), Expire),
+        (markers(false, false, true), Keep),
+        (markers(false, true, true), Expire),
+        (markers(true, true, true), Skip),
+    ];
+    for (markers, policy) in cases {
+        assert_eq!(config.policy_for(markers), policy, "for {markers:?}");
+    }
+
+    // an unmarked copy is kept whatever the policies are
+    let strict = SensitiveConfig {
+        auto_generated: Skip,
+        ..config
+    };
+    assert_eq!(strict.policy_for(ClipboardMarkers::default()), Keep);
+    assert_eq!(strict.policy_for(markers(false, true, true)), Skip);
+}
+
+#[test]
+fn app_lists_decide_which_copies_are_captured() {
+    let list = |ids: &[&str]| ids.iter().map(|id| id.to_string()).collect::<Vec<_>>();
+    let cases = [
+        // neither list: everything, including copies from unknown apps
+        (&[][..], &[][..], Some("com.apple.Safari"), true),
+        (&[], &[], None, true),
+        // deny only
+        (&["com.apple.Safari"], &[], Some("com.apple.Safari"), false),
+        (&["com.apple.Safari"], &[], Some("com.apple.Notes"), true),
+        (&["com.apple.Safari"], &[], None, true),
+        // allow only, unknown apps can't be on it
+        (&[], &["com.apple.Notes"], Some("com.apple.Notes"), true),
+        (&[], &["com.apple.Notes"], Some("com.apple.Safari"), false),
+        (&[], &["com.apple.Notes"], None, false),
+        // both, where deny wins
+        (
+            &["com.apple.Notes"],
+            &["com.apple.Notes", "com.apple.Safari"],
+            Some("com.apple.Notes"),
+            false,
+        ),
+        (
+            &["com.apple.Notes"],
+            &["com.apple.Notes", "com.apple.Safari"],
+            Some("com.apple.Safari"),
+            true,
+        ),
+        (
+            &["com.apple.Notes"],
+            &["com.apple.Safari"],
+            Some("com.apple.TextEdit"),
+            false,
+        ),
+        (&["com.apple.Notes"], &["com.apple.Safari"], None, false),
+    ];
+    for (deny, allow, bundle_id, allowed) in cases {
+        let apps = AppsConfig {
+            deny: list(deny),
+            allow: list(allow),
+        };
+        assert_eq!(
+            apps.allows(bundle_id),
+            allowed,
+            "deny {deny:?}, allow {allow:?}, copied in {bundle_id:?}"
+        );
+    }
+}
@@ -0,0 +1,175 @@
+use std::{
+    env,
+    path::PathBuf,
+    sync::{
+        Arc,
+        mpsc::{self, Sender},
+    },
+    time::Duration,
+};
+
+use futures::{StreamExt, channel::mpsc::unbounded};
+use gpui::{App, AsyncApp};
+
+use crate::{
+    backend::Backend,
+    models::{ClipboardEntry, History},
+    search::search,
+    storage::{Store, save_in_background},
+};
+
+mod client;
+mod events;
+mod protocol;
+mod server;
+#[cfg(test)]
+mod tests;
+
+pub use client::{Client, IpcError};
+pub use events::EventHub;
+pub use protocol::{ListedEntry, Request, Response, RpcError};
+
+/// Where the running instance listens for requests, `$XDG_RUNTIME_DIR/stele/stele.sock`
+/// or the per-user temporary directory on macOS
+pub fn socket_path() -> PathBuf {
+    dirs::runtime_dir()
+        .unwrap_or_else(env::temp_dir)
+        .join("stele")
+        .join("stele.sock")
+}
+
+type Pending = (Request, Sender<Result<Response, RpcError>>);
+
+pub struct IpcServer {}
+
+impl IpcServer {
+    /// Serves json-rpc requests against `history` and streams `events` to subscribers,
+    /// `on_change` is called after a request modifies history
+    pub fn spawn<F>(
+        cx: &mut App,
+        history: History,
+        backend: Backend,
+        store: Store,
+        events: Arc<EventHub>,
+        on_change: F,
+    ) where
+        F: FnMut(&mut AsyncApp) + 'static,
+    {
+        // connections are served on their own threads, requests are handed to the main thread
+        // since writing to the pasteboard and updating the panel have to happen there. The task
+        // below sleeps until one arrives.
+        let (sender, mut receiver) = unbounded::<Pending>();
+        let path = socket_path();
+        let listening = server::listen(&path, events, move |request| {
+            let unavailable = || RpcError::new(RpcError::UNAVAILABLE, "stele is shutting down");
+            let (reply, response) = mpsc::channel();
+            sender
+                .unbounded_send((request, reply))
+                .map_err(|_| unavailable())?;
+            response.recv().unwrap_or_else(|_| Err(unavailable()))
+        });
+        if let Err(err) = listening {
+            eprintln!("failed to listen on {}: {err}", path.display());
+            return;
+        }
+
+        cx.spawn(move |cx: &mut AsyncApp| {
+            let mut cx = cx.clone();
+            let mut on_change = on_change;
+            async move {
+                while let Some((request, reply)) = receiver.next().await {
+                    if request == Request::Quit {
+                        let _ = reply.send(Ok(Response::Done));
+                        // give the connection thread a moment to send the reply
+                        cx.background_executor()
+                            .timer(Duration::from_millis(100))
+                            .await;
+                        let _ = cx.update(|cx| cx.quit());
+                        return;
+                    }
+
+                    let result = handle(request, &history, &backend);
+                    if let Ok((_, true)) = result {
+                        save_in_background(
+                            cx.background_executor(),
+                            store.clone(),
+                            history.lock().unwrap().clone(),
+                        );
+                        on_change(&mut cx);
+                    }
+                    let _ = reply.send(result.map(|(response, _)| response));
+                }
+            }
+        })
+        .detach();
+    }
+}
+
+/// Runs `request` against `history`, returns the response and whether history was modified
+fn handle(
+    request: Request,
+    history: &History,
+    backend: &Backend,
+) -> Result<(Response, bool), RpcError> {
+    let mut entries = history.lock().unwrap();
+    let listed = |index: usize, entry: &ClipboardEntry| ListedEntry {
+        index,
+        entry: entry.clone(),
+    };
+
+    match request {
+        Request::Hello { .. } | Request::Subscribe | Request::Quit => Err(RpcError::new(
+            RpcError::INVALID_REQUEST,
+            "`hello`, `subscribe` and `quit` don't act on history",
+        )),
+        Request::List => Ok((
+            Response::Entries(
+                entries
+                    .iter()
+                    .enumerate()
+                    .map(|(index, entry)| listed(index, entry))
+                    .collect(),
+            ),
+            false,
+        )),
+        Request::Get { index } => entries
+            .get(index)
+            .map(|entry| (Response::Entry(listed(index, entry)), false))
+            .ok_or_else(|| RpcError::no_such_entry(index)),
+        Request::Copy { index } => {
+            let entry = entries
+                .get(index)
+                .ok_or_else(|| RpcError::no_such_entry(index))?;
+            backend.write_entry(entry);
+            Ok((Response::Done, false))
+        }
+        Request::Search { query } => Ok((
+            Response::Entries(
+                search(&entries, &query)
+                    .into_iter()
+                    .map(|m| listed(m.entry_idx, &entries[m.entry_idx]))
+                    .collect(),
+            ),
+            false,
+        )),
+        Request::Pin { index, pinned } => {
+            let entry = entries
+                .get_mut(index)
+                .ok_or_else(|| RpcError::no_such_entry(index))?;
+            entry.pinned = pinned;
+            Ok((Response::Done, true))
+        }
+        Request::Delete { index } => {
+            if index >= entries.len() {
+                return Err(RpcError::no_such_entry(index));
+            }
+            entries.remove(index);
+            Ok((Response::Done, true))
+        }
+        Request::Clear { all } => {
+            let len = entries.len();
+            entries.retain(|entry| !all && entry.pinned);
+            Ok((Response::Done, entries.len() != len))
+        }
+    }
+}
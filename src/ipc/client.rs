@@ -0,0 +1,146 @@
+use std::{
+    fmt, fs,
+    io::{self, BufRead, BufReader, Write},
+    os::unix::{
+        fs::{FileTypeExt, MetadataExt},
+        net::UnixStream,
+    },
+    path::Path,
+};
+
+use crate::ipc::{
+    events::Event,
+    protocol::{
+        Outcome, PROTOCOL_VERSION, Request, Response, RpcError, RpcNotification, RpcRequest,
+        RpcResponse,
+    },
+    server::current_uid,
+};
+
+#[derive(Debug)]
+pub enum IpcError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    /// The server rejected the call
+    Rpc(RpcError),
+}
+
+impl fmt::Display for IpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IpcError::Io(err) => write!(f, "io error: {err}"),
+            IpcError::Json(err) => write!(f, "invalid response: {err}"),
+            IpcError::Rpc(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for IpcError {}
+
+impl From<io::Error> for IpcError {
+    fn from(err: io::Error) -> Self {
+        IpcError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for IpcError {
+    fn from(err: serde_json::Error) -> Self {
+        IpcError::Json(err)
+    }
+}
+
+/// Connection to a running instance
+pub struct Client {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+    next_id: u64,
+}
+
+impl Client {
+    /// Connects to the socket at `path` and checks the server speaks the same protocol version
+    pub fn connect(path: &Path) -> Result<Self, IpcError> {
+        // don't hand requests to a socket another user put in place
+        let metadata = fs::symlink_metadata(path)?;
+        if !metadata.file_type().is_socket() || metadata.uid() != current_uid() {
+            return Err(IpcError::Io(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!(
+                    "{} isn't a socket owned by the current user",
+                    path.display()
+                ),
+            )));
+        }
+
+        let writer = UnixStream::connect(path)?;
+        let mut client = Self {
+            reader: BufReader::new(writer.try_clone()?),
+            writer,
+            next_id: 1,
+        };
+        client.request(&Request::Hello {
+            protocol: PROTOCOL_VERSION,
+        })?;
+        Ok(client)
+    }
+
+    pub fn request(&mut self, request: &Request) -> Result<Response, IpcError> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let call = RpcRequest {
+            jsonrpc: "2.0",
+            id,
+            request,
+        };
+        serde_json::to_writer(&mut self.writer, &call)?;
+        self.writer.write_all(b"\n")?;
+
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(IpcError::Io(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "connection closed before a response was received",
+            )));
+        }
+        let response = serde_json::from_str::<RpcResponse>(&line)?;
+        if response.id != id {
+            return Err(IpcError::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("expected a response to call {id}, got {}", response.id),
+            )));
+        }
+        match response.outcome {
+            Outcome::Result(value) => Ok(serde_json::from_value(value)?),
+            Outcome::Error(err) => Err(IpcError::Rpc(err)),
+        }
+    }
+
+    /// Subscribes to history changes, the connection can't make other calls afterwards
+    pub fn subscribe(mut self) -> Result<Subscription, IpcError> {
+        self.request(&Request::Subscribe)?;
+        Ok(Subscription {
+            reader: self.reader,
+        })
+    }
+}
+
+/// Events streamed by a running instance, ends when it shuts down
+pub struct Subscription {
+    reader: BufReader<UnixStream>,
+}
+
+impl Iterator for Subscription {
+    type Item = Result<Event, IpcError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut line = String::new();
+        match self.reader.read_line(&mut line) {
+            Ok(0) => None,
+            Ok(_) => Some(
+                serde_json::from_str::<RpcNotification>(&line)
+                    .map(|notification| notification.event)
+                    .map_err(IpcError::from),
+            ),
+            Err(err) => Some(Err(IpcError::Io(err))),
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        Mutex,
+        mpsc::{self, Receiver, Sender},
+    },
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::ClipboardEntry;
+
+/// A change to history, sent to subscribers as a json-rpc notification
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "method", content = "params", rename_all = "snake_case")]
+#[allow(clippy::enum_variant_names)]
+pub enum Event {
+    /// A new copy was recorded
+    EntryAdded { entry: ClipboardEntry },
+    /// Something already in history was copied again and moved to the top
+    EntryPromoted { entry: ClipboardEntry },
+    /// An entry was deleted, cleared or dropped by the history limit
+    EntryRemoved { entry: ClipboardEntry },
+}
+
+impl Event {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::EntryAdded { .. } => "entry_added",
+            Event::EntryPromoted { .. } => "entry_promoted",
+            Event::EntryRemoved { .. } => "entry_removed",
+        }
+    }
+
+    pub fn entry(&self) -> &ClipboardEntry {
+        match self {
+            Event::EntryAdded { entry }
+            | Event::EntryPromoted { entry }
+            | Event::EntryRemoved { entry } => entry,
+        }
+    }
+}
+
+/// Turns history changes into events for subscribers by comparing against the last published history
+pub struct EventHub {
+    subscribers: Mutex<Vec<Sender<Event>>>,
+    published: Mutex<Vec<ClipboardEntry>>,
+}
+
+impl EventHub {
+    pub fn new(entries: &[ClipboardEntry]) -> Self {
+        Self {
+            subscribers: Mutex::new(Vec::new()),
+            published: Mutex::new(entries.to_vec()),
+        }
+    }
+
+    pub fn subscribe(&self) -> Receiver<Event> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// Sends an event for every entry added, promoted or removed since the last call.
+    /// Entries that expire hold sensitive data and are never sent.
+    pub fn publish(&self, entries: &[ClipboardEntry]) {
+        let mut published = self.published.lock().unwrap();
+        let previous = published
+            .iter()
+            .map(|entry| (entry.id, entry))
+            .collect::<HashMap<_, _>>();
+        let current = entries.iter().map(|entry| entry.id).collect::<HashSet<_>>();
+
+        // oldest first, so subscribers see copies in the order they were made
+        let mut events = entries
+            .iter()
+            .rev()
+            .filter_map(|entry| match previous.get(&entry.id) {
+                None => Some(Event::EntryAdded {
+                    entry: entry.clone(),
+                }),
+                Some(old) if old.timestamp != entry.timestamp => Some(Event::EntryPromoted {
+                    entry: entry.clone(),
+                }),
+                Some(_) => None,
+            })
+            .collect::<Vec<_>>();
+        events.extend(
+            published
+                .iter()
+                .filter(|entry| !current.contains(&entry.id))
+                .map(|entry| Event::EntryRemoved {
+                    entry: entry.clone(),
+                }),
+        );
+        events.retain(|event| event.entry().expires_at.is_none());
+        *published = entries.to_vec();
+        drop(published);
+
+        if events.is_empty() {
+            return;
+        }
+        // subscribers that went away are dropped once sending to them fails
+        self.subscribers.lock().unwrap().retain(|subscriber| {
+            events
+                .iter()
+                .all(|event| subscriber.send(event.clone()).is_ok())
+        });
+    }
+}
@@ -0,0 +1,191 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{ipc::events::Event, models::ClipboardEntry};
+
+/// Bumped whenever a change would break existing clients, checked by the `hello` handshake
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// A method call, entries are addressed by their position in history, newest first
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "method", content = "params", rename_all = "snake_case")]
+pub enum Request {
+    /// Sent first by clients, fails if the server speaks a different protocol version
+    Hello {
+        protocol: u32,
+    },
+    List,
+    Get {
+        index: usize,
+    },
+    Copy {
+        index: usize,
+    },
+    Search {
+        query: String,
+    },
+    Pin {
+        index: usize,
+        pinned: bool,
+    },
+    Delete {
+        index: usize,
+    },
+    /// Removes every unpinned entry, or every entry when `all` is set
+    Clear {
+        all: bool,
+    },
+    /// Turns the connection into a stream of `Event` notifications, no further calls are read from it
+    Subscribe,
+    /// Shuts the running instance down
+    Quit,
+}
+
+impl Request {
+    const METHODS: &[&str] = &[
+        "hello",
+        "list",
+        "get",
+        "copy",
+        "search",
+        "pin",
+        "delete",
+        "clear",
+        "subscribe",
+        "quit",
+    ];
+
+    /// Builds a request from the method and params of a call
+    pub fn from_call(method: &str, params: Option<Value>) -> Result<Self, RpcError> {
+        if !Self::METHODS.contains(&method) {
+            return Err(RpcError::new(
+                RpcError::METHOD_NOT_FOUND,
+                format!("unknown method `{method}`"),
+            ));
+        }
+
+        let mut call = serde_json::Map::new();
+        call.insert("method".to_string(), Value::from(method));
+        if let Some(params) = params {
+            call.insert("params".to_string(), params);
+        }
+        serde_json::from_value(Value::Object(call)).map_err(|err| {
+            RpcError::new(
+                RpcError::INVALID_PARAMS,
+                format!("invalid params for `{method}`: {err}"),
+            )
+        })
+    }
+}
+
+/// The result of a successful call
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Response {
+    Entries(Vec<ListedEntry>),
+    Entry(ListedEntry),
+    Hello { protocol: u32, version: String },
+    Done,
+}
+
+/// An entry along with its position in history
+#[derive(Serialize, Deserialize)]
+pub struct ListedEntry {
+    pub index: usize,
+    #[serde(flatten)]
+    pub entry: ClipboardEntry,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i32,
+    pub message: String,
+}
+
+impl RpcError {
+    pub const PARSE_ERROR: i32 = -32700;
+    pub const INVALID_REQUEST: i32 = -32600;
+    pub const METHOD_NOT_FOUND: i32 = -32601;
+    pub const INVALID_PARAMS: i32 = -32602;
+    pub const INTERNAL_ERROR: i32 = -32603;
+    /// No entry at the requested index
+    pub const NO_SUCH_ENTRY: i32 = 1;
+    /// The client and server protocol versions differ
+    pub const UNSUPPORTED_PROTOCOL: i32 = 2;
+    /// The app is shutting down
+    pub const UNAVAILABLE: i32 = 3;
+
+    pub fn new(code: i32, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn no_such_entry(index: usize) -> Self {
+        Self::new(Self::NO_SUCH_ENTRY, format!("no entry at index {index}"))
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+/// A json-rpc 2.0 call as received, `id` is absent for notifications
+#[derive(Deserialize)]
+pub struct RpcCall {
+    pub jsonrpc: String,
+    #[serde(default)]
+    pub id: Option<Value>,
+    pub method: String,
+    #[serde(default)]
+    pub params: Option<Value>,
+}
+
+/// A json-rpc 2.0 call as sent by the client
+#[derive(Serialize)]
+pub struct RpcRequest<'a> {
+    pub jsonrpc: &'static str,
+    pub id: u64,
+    #[serde(flatten)]
+    pub request: &'a Request,
+}
+
+/// A json-rpc 2.0 notification carrying an event to a subscriber
+#[derive(Serialize, Deserialize)]
+pub struct RpcNotification {
+    pub jsonrpc: String,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RpcResponse {
+    pub jsonrpc: String,
+    pub id: Value,
+    #[serde(flatten)]
+    pub outcome: Outcome,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Result(Value),
+    Error(RpcError),
+}
+
+impl RpcResponse {
+    pub fn new(id: Value, outcome: Outcome) -> Self {
+        Self {
+            jsonrpc: "2.0".to_string(),
+            id,
+            outcome,
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    os::unix::{
+        fs::{DirBuilderExt, MetadataExt, PermissionsExt},
+        io::AsRawFd,
+        net::{UnixListener, UnixStream},
+    },
+    path::Path,
+    sync::{Arc, mpsc::Receiver},
+    thread,
+};
+
+use serde_json::Value;
+
+use crate::ipc::{
+    events::{Event, EventHub},
+    protocol::{
+        Outcome, PROTOCOL_VERSION, Request, Response, RpcCall, RpcError, RpcNotification,
+        RpcResponse,
+    },
+};
+
+/// Listens on `path` in the background, answering each newline-delimited json-rpc call with `handler`
+/// and streaming `events` to connections that subscribe.
+/// The socket's directory must only be accessible by the current user, and connections from
+/// other users are refused.
+pub fn listen<H>(path: &Path, events: Arc<EventHub>, handler: H) -> io::Result<()>
+where
+    H: Fn(Request) -> Result<Response, RpcError> + Send + Sync + 'static,
+{
+    if let Some(dir) = path.parent() {
+        secure_dir(dir)?;
+    }
+    if path.exists() {
+        // a socket nobody answers on is left over from an instance that didn't shut down cleanly
+        if UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                "another instance is already running",
+            ));
+        }
+        fs::remove_file(path)?;
+    }
+
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+
+    let handler = Arc::new(handler);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    match peer_uid(&stream) {
+                        Ok(uid) if uid == current_uid() => {}
+                        Ok(uid) => {
+                            eprintln!("refused ipc connection from user {uid}");
+                            continue;
+                        }
+                        Err(err) => {
+                            eprintln!("refused ipc connection, failed to check its user: {err}");
+                            continue;
+                        }
+                    }
+                    let handler = handler.clone();
+                    let events = events.clone();
+                    thread::spawn(move || {
+                        if let Err(err) = serve(stream, &events, handler.as_ref()) {
+                            eprintln!("ipc connection failed: {err}");
+                        }
+                    });
+                }
+                Err(err) => eprintln!("failed to accept ipc connection: {err}"),
+            }
+        }
+    });
+    Ok(())
+}
+
+pub fn current_uid() -> u32 {
+    unsafe { libc::getuid() }
+}
+
+/// Creates `dir` if needed and makes sure no other user can reach sockets in it
+fn secure_dir(dir: &Path) -> io::Result<()> {
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)?;
+
+    let metadata = fs::symlink_metadata(dir)?;
+    if !metadata.is_dir() || metadata.uid() != current_uid() {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "{} isn't a directory owned by the current user",
+                dir.display()
+            ),
+        ));
+    }
+    if metadata.mode() & 0o077 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} is accessible by other users", dir.display()),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn peer_uid(stream: &UnixStream) -> io::Result<u32> {
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = size_of::<libc::ucred>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            (&raw mut cred).cast(),
+            &mut len,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(cred.uid)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn peer_uid(stream: &UnixStream) -> io::Result<u32> {
+    let mut uid = 0;
+    let mut gid = 0;
+    if unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(uid)
+}
+
+fn serve(
+    stream: UnixStream,
+    events: &EventHub,
+    handler: &dyn Fn(Request) -> Result<Response, RpcError>,
+) -> io::Result<()> {
+    let reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let mut subscription = None;
+        let response = answer(&line, &mut |request| match request {
+            Request::Subscribe => {
+                subscription = Some(events.subscribe());
+                Ok(Response::Done)
+            }
+            request => handler(request),
+        });
+        if let Some(response) = response {
+            serde_json::to_writer(&mut writer, &response)?;
+            writer.write_all(b"\n")?;
+        }
+        if let Some(subscription) = subscription {
+            return stream_events(writer, subscription);
+        }
+    }
+    Ok(())
+}
+
+/// Writes events to a subscriber until it disconnects or the app shuts down
+fn stream_events(mut writer: UnixStream, subscription: Receiver<Event>) -> io::Result<()> {
+    for event in subscription {
+        let notification = RpcNotification {
+            jsonrpc: "2.0".to_string(),
+            event,
+        };
+        let written = serde_json::to_writer(&mut writer, &notification)
+            .map_err(io::Error::from)
+            .and_then(|()| writer.write_all(b"\n"));
+        match written {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+/// Runs a single call, returns `None` for notifications since they get no response
+fn answer(
+    line: &str,
+    handler: &mut dyn FnMut(Request) -> Result<Response, RpcError>,
+) -> Option<RpcResponse> {
+    let call = match serde_json::from_str::<Value>(line) {
+        Ok(value) => serde_json::from_value::<RpcCall>(value).map_err(|err| {
+            RpcError::new(RpcError::INVALID_REQUEST, format!("invalid request: {err}"))
+        }),
+        Err(err) => Err(RpcError::new(
+            RpcError::PARSE_ERROR,
+            format!("invalid json: {err}"),
+        )),
+    };
+    let call = match call {
+        Ok(call) if call.jsonrpc == "2.0" => call,
+        Ok(call) => {
+            let err = RpcError::new(RpcError::INVALID_REQUEST, "unsupported json-rpc version");
+            return Some(RpcResponse::new(
+                call.id.unwrap_or(Value::Null),
+                Outcome::Error(err),
+            ));
+        }
+        Err(err) => return Some(RpcResponse::new(Value::Null, Outcome::Error(err))),
+    };
+
+    let result = Request::from_call(&call.method, call.params).and_then(|request| match request {
+        Request::Hello { protocol } if protocol != PROTOCOL_VERSION => Err(RpcError::new(
+            RpcError::UNSUPPORTED_PROTOCOL,
+            format!(
+                "stele speaks protocol version {PROTOCOL_VERSION}, the client speaks version {protocol}"
+            ),
+        )),
+        Request::Hello { .. } => Ok(Response::Hello {
+            protocol: PROTOCOL_VERSION,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        }),
+        request => handler(request),
+    });
+
+    let id = call.id?;
+    let outcome = match result.map(serde_json::to_value) {
+        Ok(Ok(value)) => Outcome::Result(value),
+        Ok(Err(err)) => Outcome::Error(RpcError::new(
+            RpcError::INTERNAL_ERROR,
+            format!("failed to encode response: {err}"),
+        )),
+        Err(err) => Outcome::Error(err),
+    };
+    Some(RpcResponse::new(id, outcome))
+}
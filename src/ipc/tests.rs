@@ -0,0 +1,366 @@
+use std::{
+    env, fs,
+    io::{BufRead, BufReader, Write},
+    os::unix::{fs::PermissionsExt, net::UnixStream},
+    path::PathBuf,
+    process,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use chrono::Local;
+use serde_json::{Value, json};
+
+use crate::{
+    backend::{Backend, ClipboardBackend, MemoryBackend},
+    ipc::{
+        Client, EventHub, IpcError, Request, Response, RpcError, handle,
+        protocol::PROTOCOL_VERSION, server,
+    },
+    models::{ClipboardEntry, ClipboardItem, History, RawItem, RawRepresentation},
+};
+
+/// A fresh directory for a test's socket, kept short since socket paths are limited to ~100 bytes
+fn temp_socket_dir() -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let dir = env::temp_dir().join(format!(
+        "stele-{}-{}",
+        process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+fn text_entry(text: &str) -> ClipboardEntry {
+    ClipboardEntry::new(vec![ClipboardItem::Text(text.to_string())])
+}
+
+struct Served {
+    path: PathBuf,
+    history: History,
+    memory: Arc<MemoryBackend>,
+    events: Arc<EventHub>,
+}
+
+/// Serves `entries` over a temp socket the same way the app does, minus the main thread hop
+fn serve(entries: Vec<ClipboardEntry>) -> Served {
+    let path = temp_socket_dir().join("s.sock");
+    let events = Arc::new(EventHub::new(&entries));
+    let history: History = Arc::new(Mutex::new(entries));
+    let memory = Arc::new(MemoryBackend::new());
+    let backend: Backend = memory.clone();
+    let served = history.clone();
+    server::listen(&path, events.clone(), move |request| {
+        handle(request, &served, &backend).map(|(response, _)| response)
+    })
+    .unwrap();
+    Served {
+        path,
+        history,
+        memory,
+        events,
+    }
+}
+
+fn no_events() -> Arc<EventHub> {
+    Arc::new(EventHub::new(&[]))
+}
+
+fn texts(response: Response) -> Vec<(usize, String)> {
+    let Response::Entries(entries) = response else {
+        panic!("expected entries");
+    };
+    entries
+        .into_iter()
+        .map(|listed| match &listed.entry.items[0] {
+            ClipboardItem::Text(text) => (listed.index, text.clone()),
+            _ => panic!("expected text"),
+        })
+        .collect()
+}
+
+fn rpc_error(result: Result<Response, IpcError>) -> RpcError {
+    match result {
+        Err(IpcError::Rpc(err)) => err,
+        Err(err) => panic!("expected an rpc error, got {err}"),
+        Ok(_) => panic!("expected an rpc error"),
+    }
+}
+
+/// Sends a raw line and returns the raw response
+fn call_raw(path: &PathBuf, line: &str) -> Value {
+    let mut stream = UnixStream::connect(path).unwrap();
+    stream.write_all(line.as_bytes()).unwrap();
+    stream.write_all(b"\n").unwrap();
+    let mut response = String::new();
+    BufReader::new(stream).read_line(&mut response).unwrap();
+    serde_json::from_str(&response).unwrap()
+}
+
+#[test]
+fn lists_gets_and_searches_entries() {
+    let Served { path, .. } = serve(vec![text_entry("newest"), text_entry("older note")]);
+    let mut client = Client::connect(&path).unwrap();
+
+    assert_eq!(
+        texts(client.request(&Request::List).unwrap()),
+        [(0, "newest".to_string()), (1, "older note".to_string())]
+    );
+    let Response::Entry(listed) = client.request(&Request::Get { index: 1 }).unwrap() else {
+        panic!("expected an entry");
+    };
+    assert_eq!(listed.index, 1);
+    assert!(listed.entry.items == [ClipboardItem::Text("older note".to_string())]);
+    assert_eq!(
+        texts(
+            client
+                .request(&Request::Search {
+                    query: "note".to_string()
+                })
+                .unwrap()
+        ),
+        [(1, "older note".to_string())]
+    );
+
+    let err = rpc_error(client.request(&Request::Get { index: 2 }));
+    assert_eq!(err.code, RpcError::NO_SUCH_ENTRY);
+}
+
+#[test]
+fn mutates_history() {
+    let Served { path, history, .. } =
+        serve(vec![text_entry("a"), text_entry("b"), text_entry("c")]);
+    let mut client = Client::connect(&path).unwrap();
+
+    client
+        .request(&Request::Pin {
+            index: 2,
+            pinned: true,
+        })
+        .unwrap();
+    assert!(history.lock().unwrap()[2].pinned);
+
+    client.request(&Request::Delete { index: 0 }).unwrap();
+    assert_eq!(history.lock().unwrap().len(), 2);
+
+    client.request(&Request::Clear { all: false }).unwrap();
+    assert_eq!(
+        texts(client.request(&Request::List).unwrap()),
+        [(0, "c".to_string())]
+    );
+
+    client.request(&Request::Clear { all: true }).unwrap();
+    assert!(history.lock().unwrap().is_empty());
+}
+
+#[test]
+fn copy_writes_to_the_backend() {
+    let Served { path, memory, .. } = serve(vec![text_entry("copied")]);
+    let mut client = Client::connect(&path).unwrap();
+    let change_count = memory.change_count();
+
+    client.request(&Request::Copy { index: 0 }).unwrap();
+    assert_eq!(memory.change_count(), change_count + 1);
+    assert!(memory.read_items().unwrap() == [ClipboardItem::Text("copied".to_string())]);
+}
+
+#[test]
+fn copy_writes_every_raw_type_back() {
+    let mut entry = text_entry("bold");
+    entry.raw = vec![RawItem {
+        representations: vec![
+            RawRepresentation {
+                kind: "public.utf8-plain-text".to_string(),
+                data: b"bold".to_vec().into(),
+            },
+            RawRepresentation {
+                kind: "public.rtf".to_string(),
+                data: br"{\rtf1 {\b bold}}".to_vec().into(),
+            },
+        ],
+    }];
+    let Served { path, memory, .. } = serve(vec![entry.clone()]);
+    let mut client = Client::connect(&path).unwrap();
+
+    let Response::Entry(listed) = client.request(&Request::Get { index: 0 }).unwrap() else {
+        panic!("expected an entry");
+    };
+    assert!(listed.entry.raw == entry.raw);
+
+    client.request(&Request::Copy { index: 0 }).unwrap();
+    assert!(memory.raw() == entry.raw);
+    assert!(memory.read_items().unwrap() == entry.items);
+}
+
+#[test]
+fn rejects_invalid_calls() {
+    let Served { path, .. } = serve(Vec::new());
+
+    let response = call_raw(&path, "not json");
+    assert_eq!(response["error"]["code"], RpcError::PARSE_ERROR);
+
+    let response = call_raw(&path, r#"{"jsonrpc":"1.0","id":1,"method":"list"}"#);
+    assert_eq!(response["error"]["code"], RpcError::INVALID_REQUEST);
+    assert_eq!(response["id"], 1);
+
+    let response = call_raw(&path, r#"{"jsonrpc":"2.0","id":2,"method":"paste"}"#);
+    assert_eq!(response["error"]["code"], RpcError::METHOD_NOT_FOUND);
+
+    let response = call_raw(
+        &path,
+        r#"{"jsonrpc":"2.0","id":3,"method":"get","params":{"index":"x"}}"#,
+    );
+    assert_eq!(response["error"]["code"], RpcError::INVALID_PARAMS);
+}
+
+#[test]
+fn checks_protocol_version() {
+    let Served { path, .. } = serve(Vec::new());
+
+    let response = call_raw(
+        &path,
+        &json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "hello",
+            "params": { "protocol": PROTOCOL_VERSION },
+        })
+        .to_string(),
+    );
+    assert_eq!(response["result"]["protocol"], PROTOCOL_VERSION);
+
+    let response = call_raw(
+        &path,
+        &json!({
+            "jsonrpc": "2.0",
+            "id": 2,
+            "method": "hello",
+            "params": { "protocol": PROTOCOL_VERSION + 1 },
+        })
+        .to_string(),
+    );
+    assert_eq!(response["error"]["code"], RpcError::UNSUPPORTED_PROTOCOL);
+}
+
+#[test]
+fn answers_calls_after_a_notification() {
+    let Served { path, history, .. } = serve(vec![text_entry("a")]);
+    let mut stream = UnixStream::connect(&path).unwrap();
+    stream
+        .write_all(b"{\"jsonrpc\":\"2.0\",\"method\":\"delete\",\"params\":{\"index\":0}}\n")
+        .unwrap();
+    stream
+        .write_all(b"{\"jsonrpc\":\"2.0\",\"id\":7,\"method\":\"list\"}\n")
+        .unwrap();
+
+    let mut response = String::new();
+    BufReader::new(stream).read_line(&mut response).unwrap();
+    let response = serde_json::from_str::<Value>(&response).unwrap();
+    assert_eq!(response["id"], 7);
+    assert_eq!(response["result"], json!([]));
+    assert!(history.lock().unwrap().is_empty());
+}
+
+#[test]
+fn socket_is_private() {
+    let Served { path, .. } = serve(Vec::new());
+    let mode = |path: &PathBuf| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+    assert_eq!(mode(&path), 0o600);
+    assert_eq!(mode(&path.parent().unwrap().to_path_buf()), 0o700);
+}
+
+#[test]
+fn refuses_a_shared_directory() {
+    let dir = temp_socket_dir();
+    fs::create_dir_all(&dir).unwrap();
+    fs::set_permissions(&dir, fs::Permissions::from_mode(0o777)).unwrap();
+
+    let err = server::listen(&dir.join("s.sock"), no_events(), |_| Ok(Response::Done)).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+}
+
+#[test]
+fn replaces_a_stale_socket_but_not_a_live_one() {
+    let Served { path, .. } = serve(Vec::new());
+    assert!(server::listen(&path, no_events(), |_| Ok(Response::Done)).is_err());
+
+    let dir = temp_socket_dir();
+    let stale = dir.join("s.sock");
+    fs::create_dir_all(&dir).unwrap();
+    fs::set_permissions(&dir, fs::Permissions::from_mode(0o700)).unwrap();
+    drop(std::os::unix::net::UnixListener::bind(&stale).unwrap());
+
+    server::listen(&stale, no_events(), |_| Ok(Response::Done)).unwrap();
+    Client::connect(&stale).unwrap();
+}
+
+#[test]
+fn streams_history_changes_to_subscribers() {
+    let Served {
+        path,
+        history,
+        events,
+        ..
+    } = serve(vec![text_entry("old"), text_entry("older")]);
+    let mut subscription = Client::connect(&path).unwrap().subscribe().unwrap();
+
+    let mut entries = history.lock().unwrap();
+    let mut promoted = entries.remove(1);
+    promoted.timestamp = Local::now();
+    entries.insert(0, promoted);
+    entries.insert(0, text_entry("new"));
+    entries.pop();
+    let mut secret = text_entry("secret");
+    secret.expires_at = Some(Local::now() + Duration::from_secs(60));
+    entries.insert(0, secret);
+    events.publish(&entries);
+    drop(entries);
+
+    let mut next = || {
+        let event = subscription.next().unwrap().unwrap();
+        let ClipboardItem::Text(text) = &event.entry().items[0] else {
+            panic!("expected text");
+        };
+        (event.name(), text.clone())
+    };
+    assert_eq!(next(), ("entry_promoted", "older".to_string()));
+    assert_eq!(next(), ("entry_added", "new".to_string()));
+    assert_eq!(next(), ("entry_removed", "old".to_string()));
+
+    history.lock().unwrap().clear();
+    events.publish(&history.lock().unwrap());
+    let removed = (0..2).map(|_| next().1).collect::<Vec<_>>();
+    assert_eq!(removed, ["new", "older"]);
+}
+
+#[test]
+fn subscription_notifications_are_json_rpc() {
+    let Served { path, events, .. } = serve(Vec::new());
+    let mut stream = UnixStream::connect(&path).unwrap();
+    stream
+        .write_all(b"{\"jsonrpc\":\"2.0\",\"id\":1,\"method\":\"subscribe\"}\n")
+        .unwrap();
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    assert_eq!(
+        serde_json::from_str::<Value>(&line).unwrap()["result"],
+        Value::Null
+    );
+
+    events.publish(&[text_entry("copied")]);
+    line.clear();
+    reader.read_line(&mut line).unwrap();
+    let notification = serde_json::from_str::<Value>(&line).unwrap();
+    assert_eq!(notification["jsonrpc"], "2.0");
+    assert_eq!(notification["method"], "entry_added");
+    assert_eq!(
+        notification["params"]["entry"]["items"][0]["text"],
+        "copied"
+    );
+    assert!(notification.get("id").is_none());
+}
@@ -1,96 +1,186 @@
 use std::{
+    env, process,
     sync::{Arc, Mutex},
     time::Duration,
 };
 
-use global_hotkey::{
-    GlobalHotKeyEvent, GlobalHotKeyManager, HotKeyState,
-    hotkey::{Code, HotKey, Modifiers},
-};
+use global_hotkey::{GlobalHotKeyEvent, GlobalHotKeyManager, HotKeyState};
 use gpui::{App, AppContext, Application, AsyncApp};
+#[cfg(target_os = "macos")]
 use objc2_app_kit::{NSApplication, NSApplicationActivationPolicy};
+#[cfg(target_os = "macos")]
 use objc2_foundation::MainThreadMarker;
+#[cfg(target_os = "macos")]
 use tray_icon::{
-    Icon, TrayIconBuilder, TrayIconEvent,
-    menu::{Menu, MenuEvent, MenuItem, PredefinedMenuItem},
+    Icon, TrayIconBuilder,
+    menu::{CheckMenuItem, Menu, MenuEvent, MenuItem, PredefinedMenuItem},
 };
 
-use crate::{models::History, monitor::ClipboardMonitor, panel::Panel};
+#[cfg(target_os = "macos")]
+use crate::{
+    backend::Backend,
+    paste_stack::{StackOrder, set_stack_order, toggle_paste_stack},
+};
+use crate::{
+    backend::default_backend,
+    config::{Config, ConfigWatcher},
+    ipc::{EventHub, IpcServer},
+    models::{History, reserve_entry_ids, trim_history},
+    monitor::ClipboardMonitor,
+    panel::Panel,
+    paste_stack::{PasteStack, PasteStackWatcher},
+    storage::{open_default_store, save_in_background},
+};
 
+mod backend;
+mod cli;
+mod config;
+mod ipc;
 mod models;
 mod monitor;
 mod panel;
+mod paste_stack;
+mod search;
+mod storage;
 mod view;
 
 fn main() {
-    Application::new().run(|cx: &mut App| {
-        // Set as accessory app (no dock icon) after gpui initializes
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    // headless runs capture, storage and ipc only, without the panel, tray icon or hotkey
+    let headless = match args.as_slice() {
+        [] => false,
+        [flag] if flag == "--headless" => true,
+        _ => process::exit(cli::run(&args)),
+    };
+    let application = if headless {
+        Application::headless()
+    } else {
+        Application::new()
+    };
+
+    application.run(move |cx: &mut App| {
+        // Set as accessory app (no dock icon) after gpui initializes, headless doesn't appear at all
+        #[cfg(target_os = "macos")]
         if let Some(mtm) = MainThreadMarker::new() {
             let app = NSApplication::sharedApplication(mtm);
-            app.setActivationPolicy(NSApplicationActivationPolicy::Accessory);
+            app.setActivationPolicy(if headless {
+                NSApplicationActivationPolicy::Prohibited
+            } else {
+                NSApplicationActivationPolicy::Accessory
+            });
         }
 
-        let history: History = Arc::new(Mutex::new(Vec::new()));
-        let panel = cx.new(|cx| Panel::new(cx, history.clone()));
+        let config = Config::load().unwrap_or_else(|err| {
+            eprintln!("{err}, using default config");
+            Config::default()
+        });
+        let hotkey = config.hotkey;
+        cx.set_global(config);
+        cx.set_global(PasteStack::default());
 
-        let panel_for_monitor = panel.clone();
-        ClipboardMonitor::spawn(cx, history.clone(), move |cx: &mut AsyncApp| {
-            let _ = panel_for_monitor.update(cx, |panel, cx| panel.sync_history(cx));
+        let store = open_default_store();
+        let entries = store.load().unwrap_or_else(|err| {
+            eprintln!("failed to load history: {err}");
+            Vec::new()
         });
+        reserve_entry_ids(&entries);
+        let events = Arc::new(EventHub::new(&entries));
+        let history: History = Arc::new(Mutex::new(entries));
+        let backend = default_backend(cx.global::<Config>());
+        let panel = (!headless)
+            .then(|| cx.new(|cx| Panel::new(cx, history.clone(), backend.clone(), store.clone())));
 
-        let manager = Box::leak(Box::new(
-            GlobalHotKeyManager::new().expect("Failed to create global hotkey manager"),
-        ));
-        let hotkey = HotKey::new(Some(Modifiers::META | Modifiers::SHIFT), Code::KeyV);
-        manager.register(hotkey).unwrap();
+        let on_change = {
+            let panel = panel.clone();
+            let history = history.clone();
+            let events = events.clone();
+            move |cx: &mut AsyncApp| {
+                events.publish(&history.lock().unwrap());
+                if let Some(panel) = &panel {
+                    let _ = panel.update(cx, |panel, cx| panel.sync_history(cx));
+                }
+            }
+        };
+        ClipboardMonitor::spawn(
+            cx,
+            history.clone(),
+            backend.clone(),
+            store.clone(),
+            on_change.clone(),
+        );
+        IpcServer::spawn(
+            cx,
+            history.clone(),
+            backend.clone(),
+            store.clone(),
+            events.clone(),
+            on_change,
+        );
 
-        let receiver = GlobalHotKeyEvent::receiver().clone();
-        let panel_for_hotkey = panel.clone();
+        let manager = (!headless).then(|| {
+            let manager: &'static GlobalHotKeyManager = Box::leak(Box::new(
+                GlobalHotKeyManager::new().expect("Failed to create global hotkey manager"),
+            ));
+            manager.register(hotkey).unwrap();
+            manager
+        });
 
-        let icon_bytes = include_bytes!("../assets/stele.png");
-        let image = image::load_from_memory(icon_bytes).unwrap().to_rgba8();
-        let (width, height) = image.dimensions();
-        let rgba = image.into_raw();
-        let icon = Icon::from_rgba(rgba, width, height).unwrap();
-        let tray_menu = Menu::new();
-        tray_menu
-            .append_items(&[
-                &MenuItem::with_id("name", "stele v0.1.2", false, None),
-                &PredefinedMenuItem::separator(),
-                &MenuItem::with_id("quit", "Quit", true, None),
-            ])
-            .unwrap();
-        let _tray = Box::leak(Box::new(
-            TrayIconBuilder::new()
-                .with_icon(icon)
-                .with_icon_as_template(true)
-                .with_menu(Box::new(tray_menu))
-                .build()
-                .unwrap(),
-        ));
-        let tray_receiver = MenuEvent::receiver();
+        ConfigWatcher::spawn(cx, Config::path());
+        cx.observe_global::<Config>({
+            let history = history.clone();
+            let store = store.clone();
+            let events = events.clone();
+            let panel = panel.clone();
+            let mut registered_hotkey = hotkey;
+            move |cx| {
+                let config = cx.global::<Config>().clone();
 
-        cx.spawn({
-            let tray_receiver = tray_receiver;
-            move |cx: &mut AsyncApp| {
-                let cx = cx.clone();
-                async move {
-                    loop {
-                        while let Ok(event) = tray_receiver.try_recv() {
-                            if event.id().0.as_str() == "quit" {
-                                cx.update(|cx| cx.quit()).unwrap();
-                            }
+                if let Some(manager) = manager
+                    && config.hotkey != registered_hotkey
+                {
+                    let _ = manager.unregister(registered_hotkey);
+                    match manager.register(config.hotkey) {
+                        Ok(()) => registered_hotkey = config.hotkey,
+                        Err(err) => {
+                            eprintln!("failed to register hotkey {}: {err}", config.hotkey);
+                            let _ = manager.register(registered_hotkey);
                         }
-
-                        cx.background_executor()
-                            .timer(Duration::from_millis(16))
-                            .await;
                     }
                 }
+
+                let mut entries = history.lock().unwrap();
+                if trim_history(
+                    &mut entries,
+                    config.history_limit,
+                    config.history_byte_limit,
+                ) {
+                    save_in_background(cx.background_executor(), store.clone(), entries.clone());
+                    events.publish(&entries);
+                }
+                drop(entries);
+
+                if let Some(panel) = &panel {
+                    panel.update(cx, |panel, cx| {
+                        panel.resize(cx);
+                        panel.sync_history(cx);
+                    });
+                }
             }
         })
         .detach();
 
+        let Some(panel) = panel else {
+            return;
+        };
+        // the paste stack is controlled from the panel and tray, so it's left out when headless
+        PasteStackWatcher::spawn(cx, history.clone(), backend.clone());
+        let receiver = GlobalHotKeyEvent::receiver().clone();
+        let panel_for_hotkey = panel.clone();
+
+        // there's no tray on other platforms, `stele quit` stops the app there
+        #[cfg(target_os = "macos")]
+        spawn_tray(cx, history, backend);
+
         cx.spawn({
             let panel = panel_for_hotkey;
             let receiver = receiver;
@@ -114,3 +204,72 @@ fn main() {
         .detach();
     });
 }
+
+/// Tray icon with a menu to control the paste stack and quit the app
+#[cfg(target_os = "macos")]
+fn spawn_tray(cx: &mut App, history: History, backend: Backend) {
+    let icon_bytes = include_bytes!("../assets/stele.png");
+    let image = image::load_from_memory(icon_bytes).unwrap().to_rgba8();
+    let (width, height) = image.dimensions();
+    let rgba = image.into_raw();
+    let icon = Icon::from_rgba(rgba, width, height).unwrap();
+    let stack_item = CheckMenuItem::with_id("paste_stack", "Paste Stack", true, false, None);
+    let lifo_item = CheckMenuItem::with_id("stack_lifo", "Paste Newest First", false, false, None);
+    let tray_menu = Menu::new();
+    tray_menu
+        .append_items(&[
+            &MenuItem::with_id("name", "stele v0.1.2", false, None),
+            &PredefinedMenuItem::separator(),
+            &stack_item,
+            &lifo_item,
+            &PredefinedMenuItem::separator(),
+            &MenuItem::with_id("quit", "Quit", true, None),
+        ])
+        .unwrap();
+    // kept in sync with changes made from the panel too
+    cx.observe_global::<PasteStack>(move |cx| {
+        let stack = cx.global::<PasteStack>();
+        stack_item.set_checked(stack.is_active());
+        lifo_item.set_checked(stack.order() == StackOrder::Lifo);
+        lifo_item.set_enabled(stack.is_active());
+    })
+    .detach();
+    let _tray = Box::leak(Box::new(
+        TrayIconBuilder::new()
+            .with_icon(icon)
+            .with_icon_as_template(true)
+            .with_menu(Box::new(tray_menu))
+            .build()
+            .unwrap(),
+    ));
+    let tray_receiver = MenuEvent::receiver();
+
+    cx.spawn({
+        let tray_receiver = tray_receiver;
+        move |cx: &mut AsyncApp| {
+            let cx = cx.clone();
+            async move {
+                loop {
+                    while let Ok(event) = tray_receiver.try_recv() {
+                        match event.id().0.as_str() {
+                            "paste_stack" => cx.update(toggle_paste_stack).unwrap(),
+                            "stack_lifo" => cx
+                                .update(|cx| {
+                                    let order = cx.global::<PasteStack>().order().reversed();
+                                    set_stack_order(cx, &history, &backend, order);
+                                })
+                                .unwrap(),
+                            "quit" => cx.update(|cx| cx.quit()).unwrap(),
+                            _ => {}
+                        }
+                    }
+
+                    cx.background_executor()
+                        .timer(Duration::from_millis(16))
+                        .await;
+                }
+            }
+        }
+    })
+    .detach();
+}
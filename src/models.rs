@@ -1,29 +1,545 @@
 use std::{
+    collections::HashMap,
+    hash::{Hash, Hasher},
+    io::Cursor,
     path::PathBuf,
-    sync::{Arc, Mutex},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
 };
 
 use chrono::{DateTime, Local};
 use gpui::{ImageFormat, http_client::Url};
+use serde::{Deserialize, Serialize};
+use xxhash_rust::xxh3::Xxh3;
+
+mod blob;
+#[cfg(test)]
+mod tests;
+
+pub use blob::Blob;
 
-#[derive(Clone, PartialEq, Eq)]
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ClipboardItem {
     Text(String),
     Url(Url),
     File {
         path: PathBuf,
+        #[serde(with = "base64_bytes::option", default)]
         icon_bytes: Option<Vec<u8>>,
     },
     Image {
-        bytes: Vec<u8>,
+        bytes: Blob,
+        #[serde(with = "image_format")]
         format: ImageFormat,
+        /// Small png shown in the panel instead of decoding the original, made when it's captured
+        #[serde(with = "base64_bytes::option", default)]
+        thumbnail: Option<Vec<u8>>,
     },
+    /// Formatted text, kept along with the plain text apps offer next to it
+    RichText {
+        text: String,
+        #[serde(with = "base64_bytes")]
+        data: Vec<u8>,
+        format: RichTextFormat,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RichTextFormat {
+    Rtf,
+    Html,
+}
+
+impl RichTextFormat {
+    /// Formats in order of preference when an app offers more than one
+    pub const ALL: [RichTextFormat; 2] = [RichTextFormat::Rtf, RichTextFormat::Html];
+
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            RichTextFormat::Rtf => "text/rtf",
+            RichTextFormat::Html => "text/html",
+        }
+    }
+
+    pub fn from_mime_type(mime_type: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|format| format.mime_type() == mime_type)
+    }
+}
+
+/// One item exactly as it was on the system clipboard, every type the app offered it as
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RawItem {
+    pub representations: Vec<RawRepresentation>,
+}
+
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RawRepresentation {
+    /// Platform type identifier, e.g. `public.rtf` or `com.figma.clipboard` on macOS and a mime
+    /// type or other x11 target name on linux
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub data: Blob,
+}
+
+impl ClipboardItem {
+    /// A newly captured image, its thumbnail is added later by `add_thumbnails`
+    pub fn image(bytes: Vec<u8>, format: ImageFormat) -> Self {
+        ClipboardItem::Image {
+            bytes: Blob::new(bytes),
+            format,
+            thumbnail: None,
+        }
+    }
+
+    /// Bytes of data the item holds in memory: its text, image or formatted data and file icon.
+    /// An image moved to a file only counts its thumbnail.
+    pub fn size(&self) -> usize {
+        match self {
+            ClipboardItem::Text(text) => text.len(),
+            ClipboardItem::Url(url) => url.as_str().len(),
+            ClipboardItem::File { path, icon_bytes } => {
+                path.as_os_str().len() + icon_bytes.as_ref().map_or(0, Vec::len)
+            }
+            ClipboardItem::Image {
+                bytes, thumbnail, ..
+            } => {
+                bytes.in_memory().map_or(0, |bytes| bytes.len())
+                    + thumbnail.as_ref().map_or(0, Vec::len)
+            }
+            ClipboardItem::RichText { text, data, .. } => text.len() + data.len(),
+        }
+    }
+
+    /// Bytes of the item's data that `raw` holds a copy of in memory too
+    fn size_shared_with(&self, raw: &Blob) -> usize {
+        let Some(raw_data) = raw.in_memory() else {
+            return 0;
+        };
+        match self {
+            ClipboardItem::Text(text) if text.as_bytes() == raw_data.as_slice() => text.len(),
+            ClipboardItem::RichText { data, .. } if *data == *raw_data => data.len(),
+            // images are told apart by hash, comparing them byte by byte on every count is slow
+            ClipboardItem::Image { bytes, .. }
+                if bytes.in_memory().is_some()
+                    && bytes.len() == raw.len()
+                    && bytes.hash().ok() == raw.hash().ok() =>
+            {
+                bytes.len()
+            }
+            _ => 0,
+        }
+    }
 }
 
-#[derive(Clone)]
+/// The application a copy was made in
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceApp {
+    pub bundle_id: String,
+    pub name: Option<String>,
+    #[serde(with = "base64_bytes::option", default)]
+    pub icon_bytes: Option<Vec<u8>>,
+}
+
+impl SourceApp {
+    pub fn display_name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.bundle_id)
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct ClipboardEntry {
+    /// Stable identifier, unique for the lifetime of the stored history
+    #[serde(default)]
+    pub id: u64,
     pub timestamp: DateTime<Local>,
     pub items: Vec<ClipboardItem>,
+    /// Everything on the clipboard when the entry was captured, written back instead of `items`
+    /// so types stele doesn't understand survive a re-copy. Empty where the backend can't read it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub raw: Vec<RawItem>,
+    /// Pinned entries are listed first and never evicted by the history limit
+    #[serde(default)]
+    pub pinned: bool,
+    /// Entries captured from sensitive copies are dropped at this time and never written to disk
+    #[serde(skip)]
+    pub expires_at: Option<DateTime<Local>>,
+    #[serde(default)]
+    pub source: Option<SourceApp>,
+}
+
+static NEXT_ENTRY_ID: AtomicU64 = AtomicU64::new(1);
+
+impl ClipboardEntry {
+    pub fn new(items: Vec<ClipboardItem>) -> Self {
+        Self {
+            id: NEXT_ENTRY_ID.fetch_add(1, Ordering::Relaxed),
+            timestamp: Local::now(),
+            items,
+            raw: Vec::new(),
+            pinned: false,
+            expires_at: None,
+            source: None,
+        }
+    }
+
+    /// Bytes of data the entry holds in memory, what the history size budget is measured in.
+    /// Data also among the raw representations is counted once, and the source app's icon is
+    /// left to `history_size` since entries from the same app share it.
+    pub fn size(&self) -> usize {
+        let representations = self
+            .raw
+            .iter()
+            .flat_map(|raw| &raw.representations)
+            .collect::<Vec<_>>();
+        let items = self
+            .items
+            .iter()
+            .map(|item| {
+                let shared = representations
+                    .iter()
+                    .map(|representation| item.size_shared_with(&representation.data))
+                    .max()
+                    .unwrap_or(0);
+                item.size() - shared
+            })
+            .sum::<usize>();
+        let raw = representations
+            .iter()
+            .map(|representation| {
+                representation.kind.len()
+                    + representation.data.in_memory().map_or(0, |data| data.len())
+            })
+            .sum::<usize>();
+        items + raw
+    }
+
+    /// A copy of the entry with its rich text reduced to plain text, for pasting without formatting
+    pub fn to_plain_text(&self) -> Self {
+        let mut entry = self.clone();
+        entry.raw.clear();
+        for item in &mut entry.items {
+            if let ClipboardItem::RichText { text, .. } = item {
+                *item = ClipboardItem::Text(std::mem::take(text));
+            }
+        }
+        entry
+    }
+}
+
+/// Makes sure newly created entries never reuse the id of a loaded one
+pub fn reserve_entry_ids(entries: &[ClipboardEntry]) {
+    if let Some(max) = entries.iter().map(|entry| entry.id).max() {
+        NEXT_ENTRY_ID.fetch_max(max + 1, Ordering::Relaxed);
+    }
 }
 
 pub type History = Arc<Mutex<Vec<ClipboardEntry>>>;
+
+/// Removes entries whose expiry has passed, returns whether anything was removed
+pub fn remove_expired(entries: &mut Vec<ClipboardEntry>) -> bool {
+    let len = entries.len();
+    let now = Local::now();
+    entries.retain(|entry| entry.expires_at.is_none_or(|expires_at| expires_at > now));
+    entries.len() != len
+}
+
+/// When the first of `entries` that will expire does
+pub fn next_expiry(entries: &[ClipboardEntry]) -> Option<DateTime<Local>> {
+    entries.iter().filter_map(|entry| entry.expires_at).min()
+}
+
+/// Bytes `entries` hold in memory, counting the icon of each source app once
+pub fn history_size(entries: &[ClipboardEntry]) -> usize {
+    let mut icons = HashMap::new();
+    for source in entries.iter().filter_map(|entry| entry.source.as_ref()) {
+        if let Some(icon) = &source.icon_bytes {
+            icons.insert(source.bundle_id.as_str(), icon.len());
+        }
+    }
+    entries.iter().map(ClipboardEntry::size).sum::<usize>() + icons.values().sum::<usize>()
+}
+
+/// Removes the oldest unpinned entries until at most `limit` of them remain and the history fits
+/// in `byte_limit`, returns whether anything was removed. Pinned entries count towards the size
+/// but are never removed, and neither is the newest entry so a large copy is still recorded.
+pub fn trim_history(entries: &mut Vec<ClipboardEntry>, limit: usize, byte_limit: usize) -> bool {
+    let mut unpinned = entries.iter().filter(|entry| !entry.pinned).count();
+    let mut size = history_size(entries);
+    let mut removed = vec![false; entries.len()];
+    for (i, entry) in entries.iter().enumerate().skip(1).rev() {
+        if unpinned <= limit && size <= byte_limit {
+            break;
+        }
+        if !entry.pinned {
+            removed[i] = true;
+            unpinned -= 1;
+            size -= entry.size();
+        }
+    }
+
+    let len = entries.len();
+    let mut removed = removed.into_iter();
+    entries.retain(|_| !removed.next().unwrap());
+    entries.len() != len
+}
+
+/// Largest width or height of image thumbnails, twice the size they're shown at for retina displays
+const THUMBNAIL_SIZE: u32 = 360;
+
+/// Scales an image down to a png thumbnail, `None` if it can't be decoded
+pub fn thumbnail(bytes: &[u8]) -> Option<Vec<u8>> {
+    let image = image::load_from_memory(bytes).ok()?;
+    let image = if image.width() > THUMBNAIL_SIZE || image.height() > THUMBNAIL_SIZE {
+        image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+    } else {
+        image
+    };
+    let mut png = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+        .ok()?;
+    Some(png)
+}
+
+/// Makes thumbnails for the images in `items` that don't have one yet, slow for large images
+pub fn add_thumbnails(items: &mut [ClipboardItem]) {
+    for item in items {
+        if let ClipboardItem::Image {
+            bytes,
+            thumbnail: thumbnail @ None,
+            ..
+        } = item
+            && let Ok(bytes) = bytes.load()
+        {
+            *thumbnail = self::thumbnail(&bytes);
+        }
+    }
+}
+
+/// Lines of `text` without line endings and trailing whitespace, which copies may differ in
+fn text_lines(text: &str) -> impl Iterator<Item = &str> {
+    text.trim_end().lines().map(str::trim_end)
+}
+
+/// Hashes the contents of `items`, ignoring line endings and trailing whitespace in text and
+/// file icons, so copies that only differ in those are treated as the same
+pub fn content_hash(items: &[ClipboardItem]) -> u64 {
+    fn hash_text(text: &str, hasher: &mut Xxh3) {
+        for line in text_lines(text) {
+            line.hash(hasher);
+        }
+    }
+
+    let mut hasher = Xxh3::new();
+    for item in items {
+        match item {
+            ClipboardItem::Text(text) => {
+                0u8.hash(&mut hasher);
+                hash_text(text, &mut hasher);
+            }
+            ClipboardItem::Url(url) => {
+                1u8.hash(&mut hasher);
+                url.as_str().hash(&mut hasher);
+            }
+            ClipboardItem::File { path, .. } => {
+                2u8.hash(&mut hasher);
+                path.hash(&mut hasher);
+            }
+            ClipboardItem::Image { bytes, format, .. } => {
+                3u8.hash(&mut hasher);
+                format.mime_type().hash(&mut hasher);
+                // images that can't be read back hash alike, `same_content` tells them apart
+                bytes.hash().ok().hash(&mut hasher);
+            }
+            ClipboardItem::RichText { text, data, format } => {
+                4u8.hash(&mut hasher);
+                hash_text(text, &mut hasher);
+                format.hash(&mut hasher);
+                data.hash(&mut hasher);
+            }
+        }
+    }
+    hasher.finish()
+}
+
+/// Whether `a` and `b` hold the same contents, ignoring what `content_hash` ignores
+pub fn same_content(a: &[ClipboardItem], b: &[ClipboardItem]) -> bool {
+    fn same_text(a: &str, b: &str) -> bool {
+        text_lines(a).eq(text_lines(b))
+    }
+
+    a.len() == b.len()
+        && a.iter().zip(b).all(|pair| match pair {
+            (ClipboardItem::Text(a), ClipboardItem::Text(b)) => same_text(a, b),
+            (ClipboardItem::Url(a), ClipboardItem::Url(b)) => a == b,
+            (ClipboardItem::File { path: a, .. }, ClipboardItem::File { path: b, .. }) => a == b,
+            (
+                ClipboardItem::Image {
+                    bytes: a,
+                    format: a_format,
+                    ..
+                },
+                ClipboardItem::Image {
+                    bytes: b,
+                    format: b_format,
+                    ..
+                },
+            ) => a_format == b_format && a == b,
+            (
+                ClipboardItem::RichText {
+                    text: a,
+                    data: a_data,
+                    format: a_format,
+                },
+                ClipboardItem::RichText {
+                    text: b,
+                    data: b_data,
+                    format: b_format,
+                },
+            ) => a_format == b_format && a_data == b_data && same_text(a, b),
+            _ => false,
+        })
+}
+
+/// Finds the entry with the same contents as a new copy by hash instead of comparing it to every
+/// entry. The monitor tells it about the entries it adds, promotes and removes. History is also
+/// changed elsewhere, e.g. deleting through ipc, so positions are checked on lookup and
+/// recomputed if they went stale.
+pub struct ContentIndex {
+    /// Ids of the entries with each hash, more than one only where different contents collide
+    ids: HashMap<u64, Vec<u64>>,
+    /// Position of each entry in history
+    positions: HashMap<u64, usize>,
+}
+
+impl ContentIndex {
+    pub fn new(entries: &[ClipboardEntry]) -> Self {
+        let mut ids: HashMap<u64, Vec<u64>> = HashMap::new();
+        for entry in entries {
+            ids.entry(content_hash(&entry.items))
+                .or_default()
+                .push(entry.id);
+        }
+        let mut index = Self {
+            ids,
+            positions: HashMap::new(),
+        };
+        index.removed(entries);
+        index
+    }
+
+    /// Position in `entries` of the entry with the same contents as `items`, `hash` being their
+    /// `content_hash`. Entries that only share the hash are no match.
+    pub fn find(
+        &mut self,
+        entries: &[ClipboardEntry],
+        items: &[ClipboardItem],
+        hash: u64,
+    ) -> Option<usize> {
+        let moved = self.ids.get(&hash)?.iter().any(|id| {
+            self.positions
+                .get(id)
+                .is_some_and(|&i| entries.get(i).is_none_or(|entry| entry.id != *id))
+        });
+        if moved {
+            self.removed(entries);
+        }
+        self.ids
+            .get(&hash)?
+            .iter()
+            .filter_map(|id| self.positions.get(id).copied())
+            .find(|&i| same_content(&entries[i].items, items))
+    }
+
+    /// Records a new entry added to the front of history
+    pub fn inserted(&mut self, hash: u64, id: u64) {
+        self.ids.entry(hash).or_default().push(id);
+        self.positions.values_mut().for_each(|i| *i += 1);
+        self.positions.insert(id, 0);
+    }
+
+    /// Records the entry at `from` moving to the front of `entries`
+    pub fn promoted(&mut self, entries: &[ClipboardEntry], from: usize) {
+        for (i, entry) in entries.iter().enumerate().take(from + 1) {
+            self.positions.insert(entry.id, i);
+        }
+    }
+
+    /// Catches up with entries removed from or rearranged in `entries`
+    pub fn removed(&mut self, entries: &[ClipboardEntry]) {
+        self.positions = entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| (entry.id, i))
+            .collect();
+        self.ids.retain(|_, ids| {
+            ids.retain(|id| self.positions.contains_key(id));
+            !ids.is_empty()
+        });
+    }
+}
+
+// gpui's ImageFormat has no serde support, so it's stored by mime type
+mod image_format {
+    use gpui::ImageFormat;
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    pub fn serialize<S: Serializer>(
+        format: &ImageFormat,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(format.mime_type())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<ImageFormat, D::Error> {
+        let mime_type = String::deserialize(deserializer)?;
+        ImageFormat::from_mime_type(&mime_type)
+            .ok_or_else(|| D::Error::custom(format!("unknown image mime type: {mime_type}")))
+    }
+}
+
+mod base64_bytes {
+    use base64::{Engine, engine::general_purpose::STANDARD};
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD.decode(encoded).map_err(D::Error::custom)
+    }
+
+    pub mod option {
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(
+            bytes: &Option<Vec<u8>>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            match bytes {
+                Some(bytes) => super::serialize(bytes, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<Vec<u8>>, D::Error> {
+            #[derive(Deserialize)]
+            struct Wrapper(#[serde(with = "super")] Vec<u8>);
+
+            let wrapper = Option::<Wrapper>::deserialize(deserializer)?;
+            Ok(wrapper.map(|Wrapper(bytes)| bytes))
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de::Error as _, ser::Error as _};
+use xxhash_rust::xxh3::xxh3_64;
+
+/// Binary data such as an image original. Once it's stored the data can be moved out of memory
+/// into a file and is read back only when needed, e.g. to copy the image again. Clones share the
+/// data, so moving it out of memory applies to every copy of an entry.
+#[derive(Clone)]
+pub struct Blob(Arc<Inner>);
+
+struct Inner {
+    len: usize,
+    hash: OnceLock<u64>,
+    data: Mutex<Data>,
+}
+
+enum Data {
+    Memory(Arc<Vec<u8>>),
+    File(PathBuf),
+}
+
+impl Blob {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Self(Arc::new(Inner {
+            len: bytes.len(),
+            hash: OnceLock::new(),
+            data: Mutex::new(Data::Memory(Arc::new(bytes))),
+        }))
+    }
+
+    /// Data previously moved to `path`, `hash` being what `hash` returned for it then if it's
+    /// known
+    pub fn in_file(path: PathBuf, len: usize, hash: Option<u64>) -> Self {
+        Self(Arc::new(Inner {
+            len,
+            hash: hash.map_or_else(OnceLock::new, OnceLock::from),
+            data: Mutex::new(Data::File(path)),
+        }))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len
+    }
+
+    /// The data if it's held in memory, `None` once it was moved to a file
+    pub fn in_memory(&self) -> Option<Arc<Vec<u8>>> {
+        match &*self.0.data.lock().unwrap() {
+            Data::Memory(bytes) => Some(bytes.clone()),
+            Data::File(_) => None,
+        }
+    }
+
+    /// Hash of the data, computed once it could be read. xxh3 hashes alike on every build, so
+    /// it's stored along with the data.
+    pub fn hash(&self) -> io::Result<u64> {
+        if let Some(&hash) = self.0.hash.get() {
+            return Ok(hash);
+        }
+        let hash = xxh3_64(&self.load()?);
+        Ok(*self.0.hash.get_or_init(|| hash))
+    }
+
+    /// The data, read from its file if it was moved out of memory
+    pub fn load(&self) -> io::Result<Arc<Vec<u8>>> {
+        let path = match &*self.0.data.lock().unwrap() {
+            Data::Memory(bytes) => return Ok(bytes.clone()),
+            Data::File(path) => path.clone(),
+        };
+        fs::read(path).map(Arc::new)
+    }
+
+    /// Writes the data to `path` and drops it from memory, returns the file now holding it,
+    /// which is an earlier one if the data was already moved
+    pub fn move_to_file(&self, path: &Path) -> io::Result<PathBuf> {
+        // hashed first since the data is gone from memory afterwards
+        self.hash()?;
+        let mut data = self.0.data.lock().unwrap();
+        match &*data {
+            Data::File(existing) => Ok(existing.clone()),
+            Data::Memory(bytes) => {
+                fs::write(path, bytes.as_slice())?;
+                *data = Data::File(path.to_path_buf());
+                Ok(path.to_path_buf())
+            }
+        }
+    }
+}
+
+impl PartialEq for Blob {
+    fn eq(&self, other: &Self) -> bool {
+        if Arc::ptr_eq(&self.0, &other.0) {
+            return true;
+        }
+        // the hashes tell most blobs apart without reading them back, the data settles the rest.
+        // Data that can't be read isn't equal to anything.
+        self.len() == other.len()
+            && matches!((self.hash(), other.hash()), (Ok(a), Ok(b)) if a == b)
+            && matches!((self.load(), other.load()), (Ok(a), Ok(b)) if a == b)
+    }
+}
+
+impl Eq for Blob {}
+
+impl From<Vec<u8>> for Blob {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self::new(bytes)
+    }
+}
+
+// serialized as base64 like other binary data, so json history and ipc see the data itself
+impl Serialize for Blob {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let bytes = self.load().map_err(S::Error::custom)?;
+        serializer.serialize_str(&STANDARD.encode(bytes.as_slice()))
+    }
+}
+
+impl<'de> Deserialize<'de> for Blob {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD
+            .decode(encoded)
+            .map(Blob::new)
+            .map_err(D::Error::custom)
+    }
+}
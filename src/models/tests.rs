@@ -0,0 +1,260 @@
+use gpui::ImageFormat;
+
+use std::{env, fs, io::Cursor, process};
+
+use crate::models::{
+    Blob, ClipboardEntry, ClipboardItem, ContentIndex, RawItem, RawRepresentation, SourceApp,
+    add_thumbnails, content_hash, history_size, trim_history,
+};
+
+fn text(text: &str) -> Vec<ClipboardItem> {
+    vec![ClipboardItem::Text(text.to_string())]
+}
+
+#[test]
+fn hash_ignores_line_endings_and_trailing_whitespace() {
+    assert_eq!(
+        content_hash(&text("one\r\ntwo\n")),
+        content_hash(&text("one  \ntwo"))
+    );
+    assert_ne!(
+        content_hash(&text("one two")),
+        content_hash(&text("one\ntwo"))
+    );
+    assert_ne!(content_hash(&text("a")), content_hash(&text(" a")));
+}
+
+#[test]
+fn hash_covers_image_bytes_but_not_file_icons() {
+    let image = |bytes: Vec<u8>| vec![ClipboardItem::image(bytes, ImageFormat::Png)];
+    assert_ne!(
+        content_hash(&image(vec![1, 2])),
+        content_hash(&image(vec![1, 3]))
+    );
+
+    let file = |icon_bytes| {
+        vec![ClipboardItem::File {
+            path: "/tmp/a".into(),
+            icon_bytes,
+        }]
+    };
+    assert_eq!(
+        content_hash(&file(None)),
+        content_hash(&file(Some(vec![1])))
+    );
+}
+
+#[test]
+fn index_finds_live_entries_only() {
+    let mut entries = vec![
+        ClipboardEntry::new(text("b")),
+        ClipboardEntry::new(text("a")),
+    ];
+    let mut index = ContentIndex::new(&entries);
+    assert_eq!(
+        index.find(&entries, &text("a"), content_hash(&text("a"))),
+        Some(1)
+    );
+    assert_eq!(
+        index.find(&entries, &text("c"), content_hash(&text("c"))),
+        None
+    );
+
+    // removed without the index knowing, as deleting through ipc does
+    entries.remove(1);
+    assert_eq!(
+        index.find(&entries, &text("a"), content_hash(&text("a"))),
+        None
+    );
+
+    let entry = ClipboardEntry::new(text("a"));
+    let id = entry.id;
+    entries.insert(0, entry);
+    index.inserted(content_hash(&text("a")), id);
+    assert_eq!(
+        index.find(&entries, &text("a"), content_hash(&text("a"))),
+        Some(0)
+    );
+    assert_eq!(
+        index.find(&entries, &text("b"), content_hash(&text("b"))),
+        Some(1)
+    );
+}
+
+#[test]
+fn index_follows_promoted_and_trimmed_entries() {
+    let mut entries = ["c", "b", "a"]
+        .map(|copied| ClipboardEntry::new(text(copied)))
+        .to_vec();
+    let mut index = ContentIndex::new(&entries);
+
+    let promoted = entries.remove(2);
+    entries.insert(0, promoted);
+    index.promoted(&entries, 2);
+    for (i, copied) in ["a", "c", "b"].into_iter().enumerate() {
+        assert_eq!(
+            index.find(&entries, &text(copied), content_hash(&text(copied))),
+            Some(i)
+        );
+    }
+
+    entries.truncate(1);
+    index.removed(&entries);
+    assert_eq!(
+        index.find(&entries, &text("c"), content_hash(&text("c"))),
+        None
+    );
+    assert_eq!(
+        index.find(&entries, &text("a"), content_hash(&text("a"))),
+        Some(0)
+    );
+}
+
+#[test]
+fn index_compares_contents_sharing_a_hash() {
+    let mut entries = vec![ClipboardEntry::new(text("one\r\ntwo "))];
+    let mut index = ContentIndex::new(&entries);
+    let hash = content_hash(&entries[0].items);
+    assert_eq!(index.find(&entries, &text("one\ntwo"), hash), Some(0));
+    // stands in for a different copy that happens to collide
+    assert_eq!(index.find(&entries, &text("three"), hash), None);
+
+    // the colliding copy is recorded without losing the older entry
+    let entry = ClipboardEntry::new(text("three"));
+    let id = entry.id;
+    entries.insert(0, entry);
+    index.inserted(hash, id);
+    assert_eq!(index.find(&entries, &text("three"), hash), Some(0));
+    assert_eq!(index.find(&entries, &text("one\ntwo"), hash), Some(1));
+}
+
+#[test]
+fn blob_hashes_stay_the_same_across_builds() {
+    // the published xxh3 value for no data, it's stored in the database
+    assert_eq!(Blob::new(Vec::new()).hash().unwrap(), 0x2d06_8005_38d3_94c2);
+}
+
+#[test]
+fn blobs_sharing_a_hash_compare_their_data() {
+    let dir = env::temp_dir().join(format!("stele-blob-test-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let (a, b, missing) = (dir.join("a"), dir.join("b"), dir.join("missing"));
+    fs::write(&a, [1, 2]).unwrap();
+    fs::write(&b, [1, 3]).unwrap();
+
+    let hash = Some(Blob::new(vec![1, 2]).hash().unwrap());
+    assert!(Blob::in_file(a.clone(), 2, hash) == Blob::new(vec![1, 2]));
+    assert!(Blob::in_file(a, 2, hash) != Blob::in_file(b, 2, hash));
+
+    let unreadable = Blob::in_file(missing.clone(), 2, hash);
+    assert!(Blob::in_file(missing, 2, hash) != unreadable);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn trims_oldest_unpinned_entries_to_the_byte_budget() {
+    let image =
+        |len| ClipboardEntry::new(vec![ClipboardItem::image(vec![0; len], ImageFormat::Png)]);
+    let mut entries = vec![image(100), image(100), image(100), image(100)];
+    entries[3].pinned = true;
+    let ids = entries.iter().map(|entry| entry.id).collect::<Vec<_>>();
+
+    assert!(!trim_history(&mut entries, 10, 400));
+    assert!(trim_history(&mut entries, 10, 250));
+    assert_eq!(
+        entries.iter().map(|entry| entry.id).collect::<Vec<_>>(),
+        [ids[0], ids[3]]
+    );
+    assert_eq!(history_size(&entries), 200);
+
+    // the newest entry stays even when it's over budget on its own
+    let mut entries = vec![image(500), image(100)];
+    assert!(trim_history(&mut entries, 10, 200));
+    assert_eq!(entries.len(), 1);
+    assert_eq!(history_size(&entries), 500);
+}
+
+#[test]
+fn sizes_count_each_piece_of_data_once() {
+    let png = vec![1; 100];
+    let mut entry = ClipboardEntry::new(vec![ClipboardItem::image(png.clone(), ImageFormat::Png)]);
+    entry.raw = vec![RawItem {
+        representations: vec![
+            RawRepresentation {
+                kind: "public.png".to_string(),
+                data: png.into(),
+            },
+            RawRepresentation {
+                kind: "com.app.private".to_string(),
+                data: vec![0; 10].into(),
+            },
+        ],
+    }];
+    // the image once, the private data and the type names
+    assert_eq!(
+        entry.size(),
+        100 + 10 + "public.png".len() + "com.app.private".len()
+    );
+
+    // copies from the same app share its icon
+    let app = SourceApp {
+        bundle_id: "com.app".to_string(),
+        name: None,
+        icon_bytes: Some(vec![0; 50]),
+    };
+    let mut entries = vec![
+        ClipboardEntry::new(text("ab")),
+        ClipboardEntry::new(text("cd")),
+    ];
+    for entry in &mut entries {
+        entry.source = Some(app.clone());
+    }
+    assert_eq!(history_size(&entries), 2 + 2 + 50);
+}
+
+#[test]
+fn sizes_leave_out_data_moved_to_files() {
+    let dir = env::temp_dir().join(format!("stele-size-test-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let entry = ClipboardEntry::new(vec![ClipboardItem::image(vec![1; 100], ImageFormat::Png)]);
+    assert_eq!(entry.size(), 100);
+
+    let ClipboardItem::Image { bytes, .. } = &entry.items[0] else {
+        unreachable!()
+    };
+    bytes.move_to_file(&dir.join("image")).unwrap();
+    assert_eq!(entry.size(), 0);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn thumbnails_are_scaled_down_pngs() {
+    let mut png = Vec::new();
+    image::RgbaImage::new(1000, 500)
+        .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+        .unwrap();
+    let mut items = vec![
+        ClipboardItem::image(png, ImageFormat::Png),
+        ClipboardItem::image(vec![1, 2, 3], ImageFormat::Png),
+    ];
+    add_thumbnails(&mut items);
+
+    let ClipboardItem::Image {
+        thumbnail: Some(thumbnail),
+        ..
+    } = &items[0]
+    else {
+        panic!("expected a thumbnail");
+    };
+    let thumbnail = image::load_from_memory(thumbnail).unwrap();
+    assert_eq!((thumbnail.width(), thumbnail.height()), (360, 180));
+
+    // undecodable images are kept, just without a thumbnail
+    assert!(matches!(
+        &items[1],
+        ClipboardItem::Image {
+            thumbnail: None,
+            ..
+        }
+    ));
+}
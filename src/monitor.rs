@@ -1,171 +1,124 @@
-use crate::models::{ClipboardEntry, ClipboardItem, History};
+use crate::{
+    backend::{Backend, ChangeSignal, ChangeSource, ClipboardBackend},
+    config::{Config, MarkerPolicy},
+    models::{
+        ClipboardEntry, ClipboardItem, ContentIndex, History, RawItem, add_thumbnails,
+        content_hash, next_expiry, remove_expired, trim_history,
+    },
+    paste_stack::{PasteStack, copy_queued},
+    storage::{Store, save_in_background},
+};
 use chrono::Local;
-use dispatch2::run_on_main;
-use gpui::{App, AsyncApp, ImageFormat, http_client::Url};
-use objc2_app_kit::{
-    NSBitmapImageFileType, NSBitmapImageRep, NSPasteboard, NSPasteboardType,
-    NSPasteboardTypeFileURL, NSPasteboardTypePNG, NSPasteboardTypeString, NSPasteboardTypeTIFF,
-    NSPasteboardTypeURL, NSWorkspace,
+use gpui::{App, AsyncApp, BorrowAppContext, Task};
+use std::{
+    future::{Future, poll_fn},
+    pin::pin,
+    task::Poll,
+    time::Duration,
 };
-use objc2_foundation::{NSDictionary, NSSize, NSString, NSURL};
-use std::{path::PathBuf, time::Duration};
 
-pub const NSPASTEBOARD_TYPE_JPEG: &str = "public.jpeg";
-pub const NSPASTEBOARD_TYPE_GIF: &str = "com.compuserve.gif";
+#[cfg(test)]
+mod tests;
 
-fn get_file_icon(path: PathBuf) -> Option<Vec<u8>> {
-    run_on_main(move |_mtm| {
-        let workspace = unsafe { NSWorkspace::sharedWorkspace() };
-        let ns_image =
-            unsafe { workspace.iconForFile(&NSString::from_str(path.to_str().unwrap())) };
-        let target_size = NSSize::new(48.0, 48.0);
-        unsafe { ns_image.setSize(target_size) };
+/// Polling interval for backends that can't announce changes. Copies tend to come in bursts, so
+/// it stays at `poll_interval` for a while after each one and then backs off towards
+/// `idle_poll_interval` while nothing is copied.
+#[derive(Default)]
+pub struct PollSchedule {
+    interval: Duration,
+    /// Time since the last change was seen
+    idle_for: Duration,
+}
 
-        if let Some(tiff_data) = unsafe { ns_image.TIFFRepresentation() } {
-            if let Some(bitmap) = unsafe { NSBitmapImageRep::imageRepWithData(&tiff_data) } {
-                if let Some(png_data) = unsafe {
-                    bitmap.representationUsingType_properties(
-                        NSBitmapImageFileType::PNG,
-                        &NSDictionary::new(),
-                    )
-                } {
-                    Some(png_data.to_vec())
-                } else {
-                    None
-                }
-            } else {
-                None
-            }
+impl PollSchedule {
+    /// How long nothing has to be copied before polling slows down
+    pub const IDLE_AFTER: Duration = Duration::from_secs(5);
+
+    /// Time until the next check, given whether the last one found a change
+    pub fn next(&mut self, changed: bool, base: Duration, idle: Duration) -> Duration {
+        if changed || self.interval.is_zero() {
+            self.idle_for = Duration::ZERO;
+            self.interval = base;
         } else {
-            None
+            self.idle_for += self.interval;
+            if self.idle_for >= Self::IDLE_AFTER {
+                self.interval = (self.interval * 2).min(idle);
+            }
         }
-    })
+        // idle is never below base in a valid config
+        self.interval = self.interval.max(base);
+        self.interval
+    }
 }
 
-fn get_pasteboard_change_count() -> isize {
-    run_on_main(|_mtm| unsafe { NSPasteboard::generalPasteboard().changeCount() })
+/// Waits for `signal` to move past `count`, or for `timeout` to finish if there is one
+pub async fn wait_for_change(signal: &ChangeSignal, count: isize, timeout: Option<Task<()>>) {
+    let mut timeout = pin!(timeout);
+    poll_fn(|cx| {
+        if signal.poll_changed(cx, count).is_ready() {
+            return Poll::Ready(());
+        }
+        match timeout.as_mut().as_pin_mut() {
+            Some(timeout) => timeout.poll(cx),
+            None => Poll::Pending,
+        }
+    })
+    .await
 }
 
-fn get_pasteboard_items() -> Option<Vec<ClipboardItem>> {
-    run_on_main(|_mtm| {
-        let items = unsafe { NSPasteboard::generalPasteboard().pasteboardItems() };
+/// Clipboard contents read once a change settled
+pub struct Settled {
+    /// Change count the contents were read at
+    pub change_count: isize,
+    pub items: Option<Vec<ClipboardItem>>,
+    /// Raw data of the same copy as `items`, empty if the backend has none or it changed between
+    /// the reads
+    pub raw: Vec<RawItem>,
+}
 
-        if let Some(items) = items {
-            println!("items len: {}", items.len());
-            let collected = items
-                .iter()
-                .flat_map(|item| {
-                    unsafe { item.types() }
-                        .iter()
-                        .filter_map(move |t| {
-                            let t: &NSPasteboardType = t.as_ref();
-                            let t_string = t.to_string();
-                            println!("t_string: {}", t_string);
-                            if unsafe { t == NSPasteboardTypeString } {
-                                if let Some(ns_string) =
-                                    unsafe { item.stringForType(NSPasteboardTypeString) }
-                                {
-                                    Some(ClipboardItem::Text(ns_string.to_string()))
-                                } else {
-                                    None
-                                }
-                            } else if t == unsafe { NSPasteboardTypePNG } {
-                                if let Some(ns_data) =
-                                    unsafe { item.dataForType(NSPasteboardTypePNG) }
-                                {
-                                    Some(ClipboardItem::Image {
-                                        bytes: ns_data.to_vec(),
-                                        format: ImageFormat::Png,
-                                    })
-                                } else {
-                                    None
-                                }
-                            } else if t == unsafe { NSPasteboardTypeTIFF } {
-                                if let Some(ns_data) =
-                                    unsafe { item.dataForType(NSPasteboardTypeTIFF) }
-                                {
-                                    Some(ClipboardItem::Image {
-                                        bytes: ns_data.to_vec(),
-                                        format: ImageFormat::Tiff,
-                                    })
-                                } else {
-                                    None
-                                }
-                            } else if t == unsafe { NSPasteboardTypeURL } {
-                                if let Some(ns_string) =
-                                    unsafe { item.stringForType(NSPasteboardTypeURL) }
-                                {
-                                    match Url::parse(&ns_string.to_string()) {
-                                        Ok(url) => Some(ClipboardItem::Url(url)),
-                                        Err(_) => None,
-                                    }
-                                } else {
-                                    None
-                                }
-                            } else if t == unsafe { NSPasteboardTypeFileURL } {
-                                if let Some(ns_string) =
-                                    unsafe { item.stringForType(NSPasteboardTypeFileURL) }
-                                {
-                                    if let Some(url) = unsafe { NSURL::URLWithString(&ns_string) } {
-                                        if let Some(path) = unsafe { url.path() } {
-                                            let path_buf = PathBuf::from(path.to_string());
-                                            Some(ClipboardItem::File {
-                                                path: path_buf.clone(),
-                                                icon_bytes: get_file_icon(path_buf),
-                                            })
-                                        } else {
-                                            None
-                                        }
-                                    } else {
-                                        None
-                                    }
-                                } else {
-                                    None
-                                }
-                            } else if t_string == NSPASTEBOARD_TYPE_JPEG {
-                                if let Some(ns_data) = unsafe {
-                                    item.dataForType(
-                                        NSString::from_str(NSPASTEBOARD_TYPE_JPEG).as_ref(),
-                                    )
-                                } {
-                                    Some(ClipboardItem::Image {
-                                        bytes: ns_data.to_vec(),
-                                        format: ImageFormat::Jpeg,
-                                    })
-                                } else {
-                                    None
-                                }
-                            } else if t_string == NSPASTEBOARD_TYPE_GIF {
-                                if let Some(ns_data) = unsafe {
-                                    item.dataForType(
-                                        NSString::from_str(NSPASTEBOARD_TYPE_GIF).as_ref(),
-                                    )
-                                } {
-                                    Some(ClipboardItem::Image {
-                                        bytes: ns_data.to_vec(),
-                                        format: ImageFormat::Gif,
-                                    })
-                                } else {
-                                    None
-                                }
-                            } else {
-                                None
-                            }
-                        })
-                        .collect::<Vec<_>>()
-                })
-                .collect();
-            Some(collected)
-        } else {
-            None
+/// Reads the clipboard after a change to `change_count`, waiting out apps that announce a copy
+/// before writing its data or replace it again right away. A read is only made once the change
+/// count holds still between two looks, and is retried after the next of `delays` while it fails,
+/// comes back empty or races another change. The clipboard is read as it is once the delays run
+/// out, leaving out the raw data if it may belong to a later copy.
+pub async fn settle<F>(
+    backend: &dyn ClipboardBackend,
+    mut change_count: isize,
+    delays: &[Duration],
+    mut wait: impl FnMut(Duration) -> F,
+) -> Settled
+where
+    F: Future<Output = ()>,
+{
+    let mut delays = delays.iter();
+    loop {
+        let current = backend.change_count();
+        let last_try = delays.len() == 0;
+        if current == change_count || last_try {
+            let items = backend.read_items();
+            let raw = backend.read_raw();
+            let unchanged = backend.change_count() == current;
+            let complete = unchanged && items.as_ref().is_some_and(|items| !items.is_empty());
+            if complete || last_try {
+                return Settled {
+                    change_count: current,
+                    items,
+                    raw: if unchanged { raw } else { Vec::new() },
+                };
+            }
         }
-    })
+        // the next read has to see this count again, or whatever replaced it mid read
+        change_count = backend.change_count();
+        if let Some(&delay) = delays.next() {
+            wait(delay).await;
+        }
+    }
 }
 
 pub struct ClipboardMonitor {}
 
 impl ClipboardMonitor {
-    pub fn spawn<F>(cx: &mut App, history: History, on_change: F)
+    pub fn spawn<F>(cx: &mut App, history: History, backend: Backend, store: Store, on_change: F)
     where
         F: FnMut(&mut AsyncApp) + 'static,
     {
@@ -174,50 +127,164 @@ impl ClipboardMonitor {
             move |cx: &mut AsyncApp| {
                 let mut cx = cx.clone();
                 let history = history.clone();
+                let backend = backend.clone();
+                let store = store.clone();
                 let mut on_change = on_change;
                 async move {
-                    let mut last_change_count = get_pasteboard_change_count();
+                    let changes = backend.change_source();
+                    let mut schedule = PollSchedule::default();
+                    let mut changed = false;
+                    let mut last_change_count = backend.change_count();
+                    let mut index = ContentIndex::new(&history.lock().unwrap());
                     loop {
-                        cx.background_executor()
-                            .timer(Duration::from_millis(100))
-                            .await;
-                        let current_change_count = get_pasteboard_change_count();
-                        if current_change_count != last_change_count {
-                            let mut items = get_pasteboard_items();
-                            let mut i = 0;
-                            // retry as expensive operations may occur between change count increment and clipboard data write
-                            let retry_delays = [10, 50, 100, 150, 200, 300, 400];
-                            while items.map(|items| items.is_empty()).unwrap_or(false) && i < 7 {
-                                cx.background_executor()
-                                    .timer(Duration::from_millis(retry_delays[i]))
-                                    .await;
-                                items = get_pasteboard_items();
-                                i += 1;
+                        let Ok(config) = cx.read_global(|config: &Config, _cx| config.clone())
+                        else {
+                            break;
+                        };
+
+                        // woken early when an entry is due to expire
+                        let until_expiry = next_expiry(&history.lock().unwrap())
+                            .map(|at| (at - Local::now()).to_std().unwrap_or_default());
+                        match &changes {
+                            ChangeSource::Poll => {
+                                let interval = schedule.next(
+                                    changed,
+                                    config.poll_interval,
+                                    config.idle_poll_interval,
+                                );
+                                let interval = until_expiry
+                                    .map_or(interval, |until_expiry| interval.min(until_expiry));
+                                cx.background_executor().timer(interval).await;
                             }
+                            ChangeSource::Push(signal) => {
+                                let timeout = until_expiry.map(|until_expiry| {
+                                    cx.background_executor().timer(until_expiry)
+                                });
+                                wait_for_change(signal, last_change_count, timeout).await;
+                            }
+                        }
+
+                        let expired = remove_expired(&mut history.lock().unwrap());
+                        if expired {
+                            index.removed(&history.lock().unwrap());
+                            save_in_background(
+                                cx.background_executor(),
+                                store.clone(),
+                                history.lock().unwrap().clone(),
+                            );
+                        }
+                        let current_change_count = backend.change_count();
+                        changed = current_change_count != last_change_count;
+                        if changed {
+                            // reads wait on the app that made the copy, which may be slow to answer
+                            let (source_app, settled, markers) = cx
+                                .background_executor()
+                                .spawn({
+                                    let backend = backend.clone();
+                                    let executor = cx.background_executor().clone();
+                                    let delays = config.settle_delays.clone();
+                                    async move {
+                                        // read before settling, the user may switch apps right
+                                        // after copying
+                                        let source_app = backend.frontmost_app();
+                                        let settled = settle(
+                                            &*backend,
+                                            current_change_count,
+                                            &delays,
+                                            |delay| executor.timer(delay),
+                                        )
+                                        .await;
+                                        (source_app, settled, backend.read_markers())
+                                    }
+                                })
+                                .await;
 
-                            if let Some(items) = get_pasteboard_items() {
+                            let placed = cx
+                                .read_global(|stack: &PasteStack, _cx| stack.placed())
+                                .ok()
+                                .flatten();
+                            let policy = config.sensitive.policy_for(markers);
+                            let captured = if let Some(items) =
+                                settled.items.filter(|items| !items.is_empty())
+                                && policy != MarkerPolicy::Skip
+                                && config
+                                    .apps
+                                    .allows(source_app.as_ref().map(|app| app.bundle_id.as_str()))
+                            {
+                                let expires_at = (policy == MarkerPolicy::Expire)
+                                    .then(|| Local::now() + config.sensitive.expire_after);
+                                // decoding and hashing large images would stall the ui
+                                let (items, hash) = cx
+                                    .background_executor()
+                                    .spawn(async move {
+                                        let mut items = items;
+                                        add_thumbnails(&mut items);
+                                        let hash = content_hash(&items);
+                                        (items, hash)
+                                    })
+                                    .await;
                                 let mut history = history.lock().unwrap();
-                                if let Some(i) =
-                                    history.iter().position(|entry| entry.items == items)
-                                {
-                                    let mut old = history.remove(i);
-                                    old.timestamp = Local::now();
-                                    history.insert(0, old);
-                                } else {
-                                    history.insert(
-                                        0,
-                                        ClipboardEntry {
-                                            items,
-                                            timestamp: Local::now(),
-                                        },
+                                let (id, changed) = match index.find(&history, &items, hash) {
+                                    // the paste stack putting it back on the clipboard isn't a
+                                    // copy, it stays where it is in history
+                                    Some(i) if Some(history[i].id) == placed => {
+                                        (history[i].id, false)
+                                    }
+                                    Some(i) => {
+                                        let mut old = history.remove(i);
+                                        old.timestamp = Local::now();
+                                        // a sensitive copy of an entry that's kept for good only
+                                        // promotes it
+                                        if old.expires_at.is_some() && !old.pinned {
+                                            old.expires_at = expires_at;
+                                        }
+                                        old.source = source_app;
+                                        let id = old.id;
+                                        history.insert(0, old);
+                                        index.promoted(&history, i);
+                                        (id, true)
+                                    }
+                                    None => {
+                                        let mut entry = ClipboardEntry::new(items);
+                                        entry.raw = settled.raw;
+                                        entry.expires_at = expires_at;
+                                        entry.source = source_app;
+                                        let id = entry.id;
+                                        history.insert(0, entry);
+                                        index.inserted(hash, id);
+                                        (id, true)
+                                    }
+                                };
+                                if changed {
+                                    if trim_history(
+                                        &mut history,
+                                        config.history_limit,
+                                        config.history_byte_limit,
+                                    ) {
+                                        index.removed(&history);
+                                    }
+                                    save_in_background(
+                                        cx.background_executor(),
+                                        store.clone(),
+                                        history.clone(),
                                     );
                                 }
-                                if history.len() > 20 {
-                                    history.truncate(20);
-                                }
+                                Some(id)
+                            } else {
+                                None
+                            };
+                            if let Some(id) = captured {
+                                let _ = cx.update(|cx| {
+                                    let next = cx.update_global(|stack: &mut PasteStack, _cx| {
+                                        stack.push(id)
+                                    });
+                                    copy_queued(cx, &history, &backend, next);
+                                });
                             }
                             on_change(&mut cx);
-                            last_change_count = current_change_count;
+                            last_change_count = settled.change_count;
+                        } else if expired {
+                            on_change(&mut cx);
                         }
                     }
                 }
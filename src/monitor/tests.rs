@@ -0,0 +1,547 @@
+use std::{
+    future::{self, Future},
+    pin::pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use gpui::TestAppContext;
+
+use crate::{
+    backend::{ChangeSource, ClipboardBackend, ClipboardMarkers, MemoryBackend},
+    config::{Config, MarkerPolicy},
+    models::{Blob, ClipboardEntry, ClipboardItem, History, RawItem, RawRepresentation},
+    monitor::{ClipboardMonitor, PollSchedule, Settled, settle},
+    paste_stack::PasteStack,
+    storage::{Storage, StorageError, Store},
+};
+
+const MINUTE: Duration = Duration::from_secs(60);
+const BASE: Duration = Duration::from_millis(100);
+const IDLE: Duration = Duration::from_secs(1);
+
+/// Simulates a minute of polling at the intervals `next` gives, copies made at `copies`. Returns the number of
+/// wakeups and the longest any copy went unnoticed.
+fn poll_for_a_minute(
+    mut next: impl FnMut(bool) -> Duration,
+    copies: &[Duration],
+) -> (usize, Duration) {
+    let mut wakeups = 0;
+    let mut latency = Duration::ZERO;
+    let mut changed = false;
+    let mut now = Duration::ZERO;
+    loop {
+        let checked = now;
+        now += next(changed);
+        if now > MINUTE {
+            return (wakeups, latency);
+        }
+        wakeups += 1;
+        let seen = copies
+            .iter()
+            .filter(|&&copy| copy > checked && copy <= now)
+            .collect::<Vec<_>>();
+        for &&copy in &seen {
+            latency = latency.max(now - copy);
+        }
+        changed = !seen.is_empty();
+    }
+}
+
+/// Wakeups per minute of the monitor against the fixed 100 ms polling it replaced, run with
+/// `cargo test wakeups_per_minute -- --nocapture` to see the numbers
+#[test]
+fn wakeups_per_minute() {
+    let scenarios: [(&str, Vec<Duration>); 3] = [
+        ("idle", Vec::new()),
+        (
+            "copy every 10s",
+            (1..6).map(|i| Duration::from_secs(i * 10)).collect(),
+        ),
+        (
+            "burst of 5 copies",
+            (0..5)
+                .map(|i| Duration::from_secs(30) + Duration::from_millis(i * 700))
+                .collect(),
+        ),
+    ];
+    for (name, copies) in &scenarios {
+        let (fixed, _) = poll_for_a_minute(|_| BASE, copies);
+        let mut schedule = PollSchedule::default();
+        let (adaptive, latency) =
+            poll_for_a_minute(|changed| schedule.next(changed, BASE, IDLE), copies);
+        // pushed changes wake the monitor once per copy and never otherwise
+        let pushed = copies.len();
+        println!(
+            "{name:>18}: fixed {fixed}/min, adaptive {adaptive}/min (noticed within {latency:?}), pushed {pushed}/min"
+        );
+
+        assert_eq!(fixed, 600);
+        assert!(
+            latency <= IDLE,
+            "{name}: a copy went unnoticed for {latency:?}"
+        );
+    }
+
+    let mut schedule = PollSchedule::default();
+    let (idle, _) = poll_for_a_minute(|changed| schedule.next(changed, BASE, IDLE), &[]);
+    assert!(idle <= 120, "{idle} wakeups in an idle minute");
+}
+
+#[test]
+fn polling_speeds_up_again_after_a_change() {
+    let mut schedule = PollSchedule::default();
+    let mut idle_for = Duration::ZERO;
+    while idle_for < PollSchedule::IDLE_AFTER * 2 {
+        idle_for += schedule.next(false, BASE, IDLE);
+    }
+    assert_eq!(schedule.next(false, BASE, IDLE), IDLE);
+    assert_eq!(schedule.next(true, BASE, IDLE), BASE);
+    assert_eq!(schedule.next(false, BASE, IDLE), BASE);
+}
+
+#[test]
+fn pushed_changes_wake_the_waiting_task() {
+    let memory = MemoryBackend::new();
+    let ChangeSource::Push(signal) = memory.change_source() else {
+        panic!("expected the memory backend to push changes");
+    };
+    let count = memory.change_count();
+    let mut cx = Context::from_waker(Waker::noop());
+    assert_eq!(signal.poll_changed(&mut cx, count), Poll::Pending);
+
+    memory.set_contents(Vec::new(), Vec::new(), Default::default());
+    assert_eq!(signal.poll_changed(&mut cx, count), Poll::Ready(()));
+    assert_eq!(
+        signal.poll_changed(&mut cx, memory.change_count()),
+        Poll::Pending
+    );
+}
+
+/// Clipboard whose changes and data show up at set times on a fake clock, like apps that
+/// announce a copy before they write it
+struct LazyBackend {
+    state: Mutex<LazyState>,
+}
+
+struct LazyState {
+    now: Duration,
+    /// Time each read takes
+    read_time: Duration,
+    events: Vec<(Duration, LazyEvent)>,
+}
+
+enum LazyEvent {
+    /// The contents are cleared and the change count bumped
+    Change,
+    /// Data is written without bumping the change count
+    Write(&'static str),
+}
+
+impl LazyBackend {
+    fn new(read_time: Duration, events: Vec<(Duration, LazyEvent)>) -> Self {
+        Self {
+            state: Mutex::new(LazyState {
+                now: Duration::ZERO,
+                read_time,
+                events,
+            }),
+        }
+    }
+
+    fn now(&self) -> Duration {
+        self.state.lock().unwrap().now
+    }
+
+    fn advance(&self, by: Duration) -> future::Ready<()> {
+        self.state.lock().unwrap().now += by;
+        future::ready(())
+    }
+}
+
+impl LazyState {
+    fn happened(&self) -> impl Iterator<Item = &LazyEvent> {
+        self.events
+            .iter()
+            .filter(|(at, _)| *at <= self.now)
+            .map(|(_, event)| event)
+    }
+
+    /// The texts written since the last change, taking `read_time` to read
+    fn read(&mut self) -> Vec<String> {
+        let texts = self.happened().fold(Vec::new(), |mut texts, event| {
+            match event {
+                LazyEvent::Change => texts.clear(),
+                LazyEvent::Write(text) => texts.push(text.to_string()),
+            }
+            texts
+        });
+        self.now += self.read_time;
+        texts
+    }
+}
+
+impl ClipboardBackend for LazyBackend {
+    fn change_count(&self) -> isize {
+        let state = self.state.lock().unwrap();
+        state
+            .happened()
+            .filter(|event| matches!(event, LazyEvent::Change))
+            .count() as isize
+    }
+
+    fn read_items(&self) -> Option<Vec<ClipboardItem>> {
+        let texts = self.state.lock().unwrap().read();
+        Some(texts.into_iter().map(ClipboardItem::Text).collect())
+    }
+
+    fn read_raw(&self) -> Vec<RawItem> {
+        let texts = self.state.lock().unwrap().read();
+        texts
+            .into_iter()
+            .map(|text| RawItem {
+                representations: vec![RawRepresentation {
+                    kind: "public.utf8-plain-text".to_string(),
+                    data: Blob::new(text.into_bytes()),
+                }],
+            })
+            .collect()
+    }
+
+    fn write_entry(&self, _entry: &ClipboardEntry) {}
+}
+
+const SETTLE_DELAYS: [Duration; 4] = [
+    Duration::from_millis(10),
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+    Duration::from_millis(200),
+];
+
+/// Settles `backend` after the change the monitor saw at `change_count`, the fake clock makes
+/// every wait finish immediately
+fn settle_now(backend: &LazyBackend, change_count: isize) -> Settled {
+    let settling = pin!(settle(backend, change_count, &SETTLE_DELAYS, |delay| {
+        backend.advance(delay)
+    }));
+    match settling.poll(&mut Context::from_waker(Waker::noop())) {
+        Poll::Ready(settled) => settled,
+        Poll::Pending => panic!("settling waited on something other than the fake clock"),
+    }
+}
+
+fn texts(settled: &Settled) -> Vec<String> {
+    settled
+        .items
+        .iter()
+        .flatten()
+        .map(|item| match item {
+            ClipboardItem::Text(text) => text.clone(),
+            _ => panic!("expected text"),
+        })
+        .collect()
+}
+
+fn raw_texts(settled: &Settled) -> Vec<String> {
+    settled
+        .raw
+        .iter()
+        .flat_map(|item| &item.representations)
+        .map(|representation| {
+            String::from_utf8(representation.data.load().unwrap().to_vec()).unwrap()
+        })
+        .collect()
+}
+
+#[test]
+fn settles_on_data_written_after_the_change() {
+    let backend = LazyBackend::new(
+        Duration::ZERO,
+        vec![
+            (Duration::ZERO, LazyEvent::Change),
+            (Duration::from_millis(120), LazyEvent::Write("late")),
+        ],
+    );
+    let settled = settle_now(&backend, 1);
+    assert_eq!(texts(&settled), ["late"]);
+    assert_eq!(settled.change_count, 1);
+    // read after the 10, 50 and 100 ms waits
+    assert_eq!(backend.now(), Duration::from_millis(160));
+}
+
+#[test]
+fn waits_for_the_change_count_to_hold_still() {
+    // the copy was replaced again right after the monitor noticed it
+    let backend = LazyBackend::new(
+        Duration::ZERO,
+        vec![
+            (Duration::ZERO, LazyEvent::Change),
+            (Duration::ZERO, LazyEvent::Write("first")),
+            (Duration::ZERO, LazyEvent::Change),
+            (Duration::ZERO, LazyEvent::Write("second")),
+        ],
+    );
+    let settled = settle_now(&backend, 1);
+    assert_eq!(texts(&settled), ["second"]);
+    assert_eq!(settled.change_count, 2);
+    assert_eq!(backend.now(), Duration::from_millis(10));
+}
+
+#[test]
+fn retries_reads_that_race_another_change() {
+    let backend = LazyBackend::new(
+        Duration::from_millis(20),
+        vec![
+            (Duration::ZERO, LazyEvent::Change),
+            (Duration::ZERO, LazyEvent::Write("stale")),
+            (Duration::from_millis(15), LazyEvent::Change),
+            (Duration::from_millis(15), LazyEvent::Write("fresh")),
+        ],
+    );
+    // replaced after the items were read but before the raw data was
+    let settled = settle_now(&backend, 1);
+    assert_eq!(texts(&settled), ["fresh"]);
+    assert_eq!(raw_texts(&settled), ["fresh"]);
+    assert_eq!(settled.change_count, 2);
+}
+
+#[test]
+fn drops_raw_data_of_a_later_copy_once_the_delays_run_out() {
+    // nothing is written until the last try, where the copy is replaced between the two reads
+    let backend = LazyBackend::new(
+        Duration::from_millis(20),
+        vec![
+            (Duration::ZERO, LazyEvent::Change),
+            (Duration::from_millis(530), LazyEvent::Change),
+            (Duration::from_millis(530), LazyEvent::Write("later")),
+        ],
+    );
+    let settled = settle_now(&backend, 1);
+    assert!(settled.items.is_some_and(|items| items.is_empty()));
+    assert!(settled.raw.is_empty());
+    assert_eq!(settled.change_count, 1);
+}
+
+#[test]
+fn gives_up_once_the_delays_run_out() {
+    let backend = LazyBackend::new(Duration::ZERO, vec![(Duration::ZERO, LazyEvent::Change)]);
+    let settled = settle_now(&backend, 1);
+    assert!(settled.items.is_some_and(|items| items.is_empty()));
+    assert_eq!(backend.now(), SETTLE_DELAYS.iter().sum());
+}
+
+/// Storage that keeps every snapshot it's asked to save
+struct RecordingStorage {
+    saves: Arc<Mutex<Vec<Vec<ClipboardEntry>>>>,
+}
+
+impl Storage for RecordingStorage {
+    fn load(&mut self) -> Result<Vec<ClipboardEntry>, StorageError> {
+        Ok(Vec::new())
+    }
+
+    fn save(&mut self, entries: &[ClipboardEntry]) -> Result<(), StorageError> {
+        self.saves.lock().unwrap().push(entries.to_vec());
+        Ok(())
+    }
+}
+
+/// The monitor running against a memory clipboard on gpui's test executor
+struct Monitored {
+    cx: TestAppContext,
+    memory: Arc<MemoryBackend>,
+    history: History,
+    saves: Arc<Mutex<Vec<Vec<ClipboardEntry>>>>,
+}
+
+impl Monitored {
+    fn spawn(config: Config) -> Self {
+        let mut cx = TestAppContext::single();
+        let memory = Arc::new(MemoryBackend::new());
+        let history = History::default();
+        let saves = Arc::default();
+        let store = Store::new(RecordingStorage {
+            saves: Arc::clone(&saves),
+        });
+        cx.set_global(config);
+        cx.set_global(PasteStack::default());
+        cx.update(|cx| ClipboardMonitor::spawn(cx, history.clone(), memory.clone(), store, |_| {}));
+        cx.run_until_parked();
+        Self {
+            cx,
+            memory,
+            history,
+            saves,
+        }
+    }
+
+    /// Copies `text` in another app and lets the monitor record it
+    fn copy(&mut self, text: &str, markers: ClipboardMarkers) {
+        self.memory.set_contents(
+            vec![ClipboardItem::Text(text.to_string())],
+            Vec::new(),
+            markers,
+        );
+        self.cx.run_until_parked();
+    }
+
+    /// Text of the recorded entries, newest first
+    fn texts(&self) -> Vec<String> {
+        entry_texts(&self.history.lock().unwrap())
+    }
+
+    /// Text of the entries in the latest save
+    fn saved_texts(&self) -> Vec<String> {
+        entry_texts(self.saves.lock().unwrap().last().map_or(&[], Vec::as_slice))
+    }
+
+    /// Whether `text` was in any snapshot handed to storage
+    fn ever_saved(&self, text: &str) -> bool {
+        self.saves
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|entries| entry_texts(entries).iter().any(|saved| saved == text))
+    }
+
+    /// Lets real and executor time pass, expiry is checked against the wall clock
+    fn wait(&mut self, duration: Duration) {
+        std::thread::sleep(duration);
+        self.cx.executor().advance_clock(duration);
+        self.cx.run_until_parked();
+    }
+}
+
+fn entry_texts(entries: &[ClipboardEntry]) -> Vec<String> {
+    entries
+        .iter()
+        .flat_map(|entry| &entry.items)
+        .map(|item| match item {
+            ClipboardItem::Text(text) => text.clone(),
+            _ => panic!("expected text"),
+        })
+        .collect()
+}
+
+#[test]
+fn records_copies_newest_first() {
+    let mut monitored = Monitored::spawn(Config::default());
+    monitored.copy("first", ClipboardMarkers::default());
+    monitored.copy("second", ClipboardMarkers::default());
+    assert_eq!(monitored.texts(), ["second", "first"]);
+    assert_eq!(monitored.saved_texts(), ["second", "first"]);
+
+    // copying it again moves it back to the top instead of adding another entry
+    monitored.copy("first", ClipboardMarkers::default());
+    assert_eq!(monitored.texts(), ["first", "second"]);
+}
+
+const EXPIRE_AFTER: Duration = Duration::from_millis(50);
+
+/// Skips concealed copies, expires transient ones and keeps auto-generated ones
+fn sensitive_config() -> Config {
+    let mut config = Config::default();
+    config.sensitive.concealed = MarkerPolicy::Skip;
+    config.sensitive.transient = MarkerPolicy::Expire;
+    config.sensitive.auto_generated = MarkerPolicy::Keep;
+    config.sensitive.expire_after = EXPIRE_AFTER;
+    config
+}
+
+const CONCEALED: ClipboardMarkers = ClipboardMarkers {
+    concealed: true,
+    transient: false,
+    auto_generated: false,
+};
+const TRANSIENT: ClipboardMarkers = ClipboardMarkers {
+    concealed: false,
+    transient: true,
+    auto_generated: false,
+};
+const AUTO_GENERATED: ClipboardMarkers = ClipboardMarkers {
+    concealed: false,
+    transient: false,
+    auto_generated: true,
+};
+
+#[test]
+fn skipped_copies_are_not_recorded() {
+    let mut monitored = Monitored::spawn(sensitive_config());
+    monitored.copy("password", CONCEALED);
+    monitored.copy(
+        "generated password",
+        ClipboardMarkers {
+            auto_generated: true,
+            ..CONCEALED
+        },
+    );
+    assert!(monitored.texts().is_empty());
+    assert!(!monitored.ever_saved("password"));
+    assert!(!monitored.ever_saved("generated password"));
+}
+
+#[test]
+fn expiring_copies_are_dropped_and_never_saved() {
+    let mut monitored = Monitored::spawn(sensitive_config());
+    monitored.copy("kept", ClipboardMarkers::default());
+    monitored.copy("one time code", TRANSIENT);
+    assert_eq!(monitored.texts(), ["one time code", "kept"]);
+    assert_eq!(monitored.saved_texts(), ["kept"]);
+
+    monitored.wait(EXPIRE_AFTER * 2);
+    assert_eq!(monitored.texts(), ["kept"]);
+    assert_eq!(monitored.saved_texts(), ["kept"]);
+    assert!(!monitored.ever_saved("one time code"));
+}
+
+#[test]
+fn kept_markers_record_normally() {
+    let mut monitored = Monitored::spawn(sensitive_config());
+    monitored.copy("generated", AUTO_GENERATED);
+    monitored.wait(EXPIRE_AFTER * 2);
+    assert_eq!(monitored.texts(), ["generated"]);
+    assert_eq!(monitored.saved_texts(), ["generated"]);
+}
+
+#[test]
+fn sensitive_repeats_of_kept_entries_only_promote_them() {
+    let mut monitored = Monitored::spawn(sensitive_config());
+    monitored.copy("address", ClipboardMarkers::default());
+    monitored.copy("pinned", ClipboardMarkers::default());
+    monitored.history.lock().unwrap()[0].pinned = true;
+    monitored.copy("other", ClipboardMarkers::default());
+
+    monitored.copy("address", TRANSIENT);
+    monitored.copy("pinned", TRANSIENT);
+    assert_eq!(monitored.texts(), ["pinned", "address", "other"]);
+
+    monitored.wait(EXPIRE_AFTER * 2);
+    assert_eq!(monitored.texts(), ["pinned", "address", "other"]);
+    assert_eq!(monitored.saved_texts(), ["pinned", "address", "other"]);
+}
+
+#[test]
+fn the_paste_stacks_own_copies_are_not_promoted() {
+    let mut monitored = Monitored::spawn(Config::default());
+    monitored
+        .cx
+        .update_global(|stack: &mut PasteStack, _cx| stack.set_active(true));
+    monitored.copy("first", ClipboardMarkers::default());
+    // the stack puts "first" back on the clipboard, which isn't a copy of it
+    monitored.copy("second", ClipboardMarkers::default());
+    assert_eq!(
+        entry_texts(&[ClipboardEntry::new(monitored.memory.read_items().unwrap())]),
+        ["first"]
+    );
+    assert_eq!(monitored.texts(), ["second", "first"]);
+    assert_eq!(monitored.saved_texts(), ["second", "first"]);
+    monitored.cx.read_global(|stack: &PasteStack, _cx| {
+        assert_eq!(stack.queued().len(), 2);
+        assert_eq!(stack.placed(), None);
+    });
+
+    // copying it for real still does
+    monitored.copy("first", ClipboardMarkers::default());
+    assert_eq!(monitored.texts(), ["first", "second"]);
+}
@@ -1,51 +1,56 @@
+use std::rc::Rc;
+
+#[cfg(target_os = "macos")]
 use dispatch2::run_on_main;
+#[cfg(target_os = "macos")]
+use gpui::point;
 use gpui::{
-    App, AppContext, Bounds, WindowBackgroundAppearance, WindowBounds, WindowHandle, WindowKind,
-    WindowOptions, point, px, size,
+    App, AppContext, Bounds, Pixels, PlatformDisplay, Point, WindowBackgroundAppearance,
+    WindowBounds, WindowHandle, WindowKind, WindowOptions, px, size,
 };
+#[cfg(target_os = "macos")]
 use objc2_app_kit::{NSEvent, NSScreen};
+#[cfg(target_os = "macos")]
 use objc2_foundation::{NSArray, NSString};
 
-use crate::{models::History, view::View};
+use crate::{
+    backend::Backend, config::Config, models::History, paste_stack::PasteStack, storage::Store,
+    view::View,
+};
 
 pub struct Panel {
     window: WindowHandle<View>,
     history: History,
+    backend: Backend,
+    store: Store,
 }
 
 impl Panel {
-    const WIDTH: f32 = 220.0;
-    const HEIGHT: f32 = 250.0;
-
-    pub fn new(cx: &mut App, history: History) -> Self {
-        let window = Self::open_window(cx, history.clone());
-        Self { window, history }
+    pub fn new(cx: &mut App, history: History, backend: Backend, store: Store) -> Self {
+        let window = Self::open_window(cx, history.clone(), backend.clone(), store.clone());
+        Self {
+            window,
+            history,
+            backend,
+            store,
+        }
     }
 
-    fn open_window(cx: &mut App, history: History) -> WindowHandle<View> {
-        let mouse_pos = run_on_main(|_mtm| unsafe { NSEvent::mouseLocation() });
-
-        let displays = cx.displays();
-        let active = displays.iter().find(move |display| {
-            let bounds = display.bounds();
-            mouse_pos.x >= bounds.origin.x.to_f64()
-                && mouse_pos.x <= (bounds.origin.x + bounds.size.width).to_f64()
-                && mouse_pos.y >= bounds.origin.y.to_f64()
-                && mouse_pos.y <= (bounds.origin.y + bounds.size.height).to_f64()
-        });
-
-        let bounds = if let Some(display) = active {
-            // appkit gives relative to bottom of screen, gpui expects relative to top of screen
-            let bounds = display.bounds();
-            let flipped_y =
-                2.0 * bounds.origin.y.to_f64() + bounds.size.height.to_f64() - mouse_pos.y;
-            Some((mouse_pos.x, flipped_y));
-            Bounds::new(
-                point(px(mouse_pos.x as f32), px(flipped_y as f32)),
-                size(px(Self::WIDTH), px(Self::HEIGHT)),
-            )
-        } else {
-            Bounds::centered(None, size(px(Self::WIDTH), px(Self::HEIGHT)), cx)
+    fn open_window(
+        cx: &mut App,
+        history: History,
+        backend: Backend,
+        store: Store,
+    ) -> WindowHandle<View> {
+        let panel_size = {
+            let panel = cx.global::<Config>().panel;
+            size(px(panel.width), px(panel.height))
+        };
+        let cursor = cursor_position(cx);
+        let active = cursor.as_ref().map(|(display, _)| display.clone());
+        let bounds = match cursor {
+            Some((_, position)) => Bounds::new(position, panel_size),
+            None => Bounds::centered(None, panel_size, cx),
         };
 
         let window = cx
@@ -56,18 +61,18 @@ impl Panel {
                     kind: WindowKind::PopUp,
                     window_bounds: Some(WindowBounds::Windowed(bounds)),
                     window_background: WindowBackgroundAppearance::Blurred,
-                    display_id: if let Some(display) = active {
-                        Some(display.id())
-                    } else {
-                        None
-                    },
+                    display_id: active.map(|display| display.id()),
                     ..Default::default()
                 },
                 move |_window, cx| {
                     let history = history.clone();
+                    let backend = backend.clone();
+                    let store = store.clone();
                     cx.new(|cx| {
-                        let mut view = View::new(cx);
-                        view.update_snapshot(history.clone());
+                        cx.observe_global::<PasteStack>(|_view, cx| cx.notify())
+                            .detach();
+                        let mut view = View::new(cx, history, backend, store);
+                        view.update_snapshot();
                         view
                     })
                 },
@@ -75,7 +80,7 @@ impl Panel {
             .unwrap();
 
         window
-            .update(cx, |view, window, cx| {
+            .update(cx, |view, window, _cx| {
                 window.focus(&view.focus_handle());
             })
             .unwrap();
@@ -91,22 +96,59 @@ impl Panel {
     }
 
     pub fn show(&mut self, cx: &mut App) {
-        *self = Self::new(cx, self.history.clone());
+        *self = Self::new(
+            cx,
+            self.history.clone(),
+            self.backend.clone(),
+            self.store.clone(),
+        );
     }
 
     pub fn toggle(&mut self, cx: &mut App) {
-        if let Some(_) = self.window.is_active(cx) {
+        if self.window.is_active(cx).is_some() {
             self.hide(cx);
         } else {
             self.show(cx);
         }
     }
 
+    /// Applies the configured size to the open window
+    pub fn resize(&mut self, cx: &mut App) {
+        let panel = cx.global::<Config>().panel;
+        let _ = self.window.update(cx, |_view, window, _cx| {
+            window.resize(size(px(panel.width), px(panel.height)));
+        });
+    }
+
     pub fn sync_history(&mut self, cx: &mut App) {
-        let history = self.history.clone();
         let _ = self.window.update(cx, |view, window, _cx| {
-            view.update_snapshot(history);
+            view.update_snapshot();
             window.refresh();
         });
     }
 }
+
+/// The display under the mouse cursor and the cursor's position on it
+#[cfg(target_os = "macos")]
+fn cursor_position(cx: &App) -> Option<(Rc<dyn PlatformDisplay>, Point<Pixels>)> {
+    let mouse_pos = run_on_main(|_mtm| unsafe { NSEvent::mouseLocation() });
+
+    let display = cx.displays().into_iter().find(move |display| {
+        let bounds = display.bounds();
+        mouse_pos.x >= bounds.origin.x.to_f64()
+            && mouse_pos.x <= (bounds.origin.x + bounds.size.width).to_f64()
+            && mouse_pos.y >= bounds.origin.y.to_f64()
+            && mouse_pos.y <= (bounds.origin.y + bounds.size.height).to_f64()
+    })?;
+
+    // appkit gives relative to bottom of screen, gpui expects relative to top of screen
+    let bounds = display.bounds();
+    let flipped_y = 2.0 * bounds.origin.y.to_f64() + bounds.size.height.to_f64() - mouse_pos.y;
+    Some((display, point(px(mouse_pos.x as f32), px(flipped_y as f32))))
+}
+
+/// gpui doesn't expose the cursor position elsewhere, so the panel opens centered
+#[cfg(not(target_os = "macos"))]
+fn cursor_position(_cx: &App) -> Option<(Rc<dyn PlatformDisplay>, Point<Pixels>)> {
+    None
+}
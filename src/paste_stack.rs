@@ -0,0 +1,188 @@
+use std::{collections::VecDeque, time::Duration};
+
+use gpui::{App, AsyncApp, BorrowAppContext, Global};
+
+use crate::{backend::Backend, models::History, monitor::wait_for_change};
+
+#[cfg(test)]
+mod tests;
+
+/// Order queued copies are pasted in
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StackOrder {
+    /// Oldest copy first, e.g. to fill in a form in the order the values were copied
+    #[default]
+    Fifo,
+    /// Newest copy first
+    Lifo,
+}
+
+impl StackOrder {
+    pub fn reversed(self) -> Self {
+        match self {
+            StackOrder::Fifo => StackOrder::Lifo,
+            StackOrder::Lifo => StackOrder::Fifo,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            StackOrder::Fifo => "oldest first",
+            StackOrder::Lifo => "newest first",
+        }
+    }
+}
+
+/// Paste stack mode: while it's on copies are queued, and every paste puts the next queued copy
+/// on the clipboard until the queue runs out
+#[derive(Default)]
+pub struct PasteStack {
+    active: bool,
+    order: StackOrder,
+    /// Ids of the queued entries, oldest copy first. The next one is on the clipboard.
+    queue: VecDeque<u64>,
+    /// Entry the stack just put on the clipboard, its copy isn't queued again
+    placed: Option<u64>,
+}
+
+impl Global for PasteStack {}
+
+impl PasteStack {
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    pub fn order(&self) -> StackOrder {
+        self.order
+    }
+
+    /// Turns the mode on or off, either way starting from an empty queue
+    pub fn set_active(&mut self, active: bool) {
+        self.active = active;
+        self.queue.clear();
+        self.placed = None;
+    }
+
+    /// Changes which copy is pasted next, returns the entry to put on the clipboard for it
+    pub fn set_order(&mut self, order: StackOrder) -> Option<u64> {
+        self.order = order;
+        self.place(None)
+    }
+
+    /// The entry pasted next
+    pub fn next(&self) -> Option<u64> {
+        match self.order {
+            StackOrder::Fifo => self.queue.front().copied(),
+            StackOrder::Lifo => self.queue.back().copied(),
+        }
+    }
+
+    /// Entry the stack put on the clipboard whose copy hasn't been seen yet
+    pub fn placed(&self) -> Option<u64> {
+        self.placed
+    }
+
+    /// Queued entries in the order they'll be pasted
+    pub fn queued(&self) -> Vec<u64> {
+        match self.order {
+            StackOrder::Fifo => self.queue.iter().copied().collect(),
+            StackOrder::Lifo => self.queue.iter().rev().copied().collect(),
+        }
+    }
+
+    /// Queues a copy of entry `id`, moving it to the end if it was queued already. Returns the
+    /// entry to put on the clipboard when it's not the one just copied.
+    pub fn push(&mut self, id: u64) -> Option<u64> {
+        if !self.active || self.placed.take() == Some(id) {
+            return None;
+        }
+        self.queue.retain(|&queued| queued != id);
+        self.queue.push_back(id);
+        self.place(Some(id))
+    }
+
+    /// Drops the entry that was just pasted, returns the one to put on the clipboard next
+    pub fn pasted(&mut self) -> Option<u64> {
+        if !self.active {
+            return None;
+        }
+        match self.order {
+            StackOrder::Fifo => self.queue.pop_front(),
+            StackOrder::Lifo => self.queue.pop_back(),
+        };
+        self.place(None)
+    }
+
+    /// The next entry if it isn't `on_clipboard` already, remembered so its copy is ignored
+    fn place(&mut self, on_clipboard: Option<u64>) -> Option<u64> {
+        self.placed = self.next().filter(|&next| Some(next) != on_clipboard);
+        self.placed
+    }
+}
+
+/// Turns paste stack mode on or off
+pub fn toggle_paste_stack(cx: &mut App) {
+    cx.update_global(|stack: &mut PasteStack, _cx| {
+        let active = !stack.is_active();
+        stack.set_active(active);
+    });
+}
+
+/// Changes the order queued copies are pasted in, putting the one that's now next on the clipboard
+pub fn set_stack_order(cx: &mut App, history: &History, backend: &Backend, order: StackOrder) {
+    let next = cx.update_global(|stack: &mut PasteStack, _cx| stack.set_order(order));
+    copy_queued(cx, history, backend, next);
+}
+
+/// Puts entry `id` on the clipboard, skipping over queued entries that were removed from history
+/// since they were copied
+pub fn copy_queued(cx: &mut App, history: &History, backend: &Backend, id: Option<u64>) {
+    let mut id = id;
+    while let Some(next) = id {
+        let entry = history
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|entry| entry.id == next)
+            .cloned();
+        match entry {
+            Some(entry) => return backend.write_entry(&entry),
+            None => id = cx.update_global(|stack: &mut PasteStack, _cx| stack.pasted()),
+        }
+    }
+}
+
+pub struct PasteStackWatcher {}
+
+impl PasteStackWatcher {
+    /// Time for a paste to finish reading the clipboard before the next copy replaces it, apps
+    /// often read it more than once, e.g. once per type
+    const PASTE_SETTLE: Duration = Duration::from_millis(300);
+
+    /// Moves the paste stack along whenever another app pastes, where the backend can tell
+    pub fn spawn(cx: &mut App, history: History, backend: Backend) {
+        let Some(pastes) = backend.paste_signal() else {
+            return;
+        };
+        cx.spawn(move |cx: &mut AsyncApp| {
+            let cx = cx.clone();
+            async move {
+                let mut count = pastes.count();
+                loop {
+                    wait_for_change(&pastes, count, None).await;
+                    cx.background_executor().timer(Self::PASTE_SETTLE).await;
+                    count = pastes.count();
+
+                    let updated = cx.update(|cx| {
+                        let next = cx.update_global(|stack: &mut PasteStack, _cx| stack.pasted());
+                        copy_queued(cx, &history, &backend, next);
+                    });
+                    if updated.is_err() {
+                        break;
+                    }
+                }
+            }
+        })
+        .detach();
+    }
+}
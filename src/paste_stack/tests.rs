@@ -0,0 +1,72 @@
+use crate::paste_stack::{PasteStack, StackOrder};
+
+fn active(order: StackOrder) -> PasteStack {
+    let mut stack = PasteStack::default();
+    stack.set_active(true);
+    stack.set_order(order);
+    stack
+}
+
+#[test]
+fn fifo_keeps_the_oldest_copy_on_the_clipboard() {
+    let mut stack = active(StackOrder::Fifo);
+    assert_eq!(stack.push(1), None);
+    // copying 2 replaced 1 on the clipboard, so 1 is put back
+    assert_eq!(stack.push(2), Some(1));
+    // the monitor then sees the stack's own copy of 1, which isn't queued again
+    assert_eq!(stack.push(1), None);
+    assert_eq!(stack.push(3), Some(1));
+    assert_eq!(stack.push(1), None);
+    assert_eq!(stack.queued(), [1, 2, 3]);
+
+    assert_eq!(stack.pasted(), Some(2));
+    assert_eq!(stack.push(2), None);
+    assert_eq!(stack.pasted(), Some(3));
+    assert_eq!(stack.push(3), None);
+    assert_eq!(stack.pasted(), None);
+    assert!(stack.queued().is_empty());
+}
+
+#[test]
+fn lifo_pastes_the_newest_copy_first() {
+    let mut stack = active(StackOrder::Lifo);
+    for id in [1, 2, 3] {
+        // the newest copy is already on the clipboard
+        assert_eq!(stack.push(id), None);
+    }
+    assert_eq!(stack.queued(), [3, 2, 1]);
+
+    assert_eq!(stack.pasted(), Some(2));
+    assert_eq!(stack.push(2), None);
+    // a copy made mid way goes on top
+    assert_eq!(stack.push(4), None);
+    assert_eq!(stack.queued(), [4, 2, 1]);
+    assert_eq!(stack.pasted(), Some(2));
+}
+
+#[test]
+fn copying_an_entry_again_requeues_it() {
+    let mut stack = active(StackOrder::Fifo);
+    stack.push(1);
+    stack.push(2);
+    stack.push(1);
+    // 1 was the stack's own copy, a later copy of it by the user moves it to the end
+    stack.push(1);
+    assert_eq!(stack.queued(), [2, 1]);
+}
+
+#[test]
+fn switching_order_or_mode_changes_what_is_placed() {
+    let mut stack = active(StackOrder::Fifo);
+    stack.push(1);
+    stack.push(2);
+    stack.push(1);
+    assert_eq!(stack.set_order(StackOrder::Lifo), Some(2));
+    assert_eq!(stack.queued(), [2, 1]);
+
+    stack.set_active(false);
+    assert!(stack.queued().is_empty());
+    assert_eq!(stack.push(3), None);
+    assert_eq!(stack.pasted(), None);
+    assert!(stack.queued().is_empty());
+}
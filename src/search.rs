@@ -0,0 +1,106 @@
+use std::cmp::Reverse;
+
+use fuzzy_matcher::{FuzzyMatcher, skim::SkimMatcherV2};
+
+use crate::models::{ClipboardEntry, ClipboardItem};
+
+#[cfg(test)]
+mod tests;
+
+/// An entry that matched the search query
+pub struct SearchMatch {
+    /// Index into the searched entries
+    pub entry_idx: usize,
+    /// Item the query matched best, `None` when the query is empty
+    pub item_idx: Option<usize>,
+    /// Char indices of the matched characters in the item's `search_text`
+    pub positions: Vec<usize>,
+    score: i64,
+}
+
+/// The text an item can be found by: its contents, url or file name
+pub fn search_text(item: &ClipboardItem) -> Option<String> {
+    match item {
+        ClipboardItem::Text(text) | ClipboardItem::RichText { text, .. } => Some(text.clone()),
+        ClipboardItem::Url(url) => Some(url.to_string()),
+        ClipboardItem::File { path, .. } => path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned()),
+        ClipboardItem::Image { .. } => None,
+    }
+}
+
+/// Splits `app:<name>` filters out of `query`, returning them lowercased along with the rest
+fn parse_query(query: &str) -> (Vec<String>, String) {
+    let mut apps = Vec::new();
+    let mut rest = Vec::new();
+    for word in query.split_whitespace() {
+        match word.strip_prefix("app:") {
+            Some(app) if !app.is_empty() => apps.push(app.to_lowercase()),
+            Some(_) => {}
+            None => rest.push(word),
+        }
+    }
+    (apps, rest.join(" "))
+}
+
+/// Whether the entry was copied from an app whose name or bundle id contains every filter
+fn matches_apps(entry: &ClipboardEntry, apps: &[String]) -> bool {
+    if apps.is_empty() {
+        return true;
+    }
+    let Some(source) = &entry.source else {
+        return false;
+    };
+    let bundle_id = source.bundle_id.to_lowercase();
+    let name = source.name.as_deref().unwrap_or_default().to_lowercase();
+    apps.iter()
+        .all(|app| bundle_id.contains(app.as_str()) || name.contains(app.as_str()))
+}
+
+/// Fuzzy matches `query` against every entry, best match first.
+/// `app:<name>` words only keep entries copied from a matching app.
+/// An empty query matches everything in history order.
+pub fn search(entries: &[ClipboardEntry], query: &str) -> Vec<SearchMatch> {
+    let (apps, query) = parse_query(query);
+    let entries = entries
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| matches_apps(entry, &apps));
+
+    if query.is_empty() {
+        return entries
+            .map(|(entry_idx, _)| SearchMatch {
+                entry_idx,
+                item_idx: None,
+                positions: Vec::new(),
+                score: 0,
+            })
+            .collect();
+    }
+
+    let matcher = SkimMatcherV2::default().smart_case();
+    let mut matches = entries
+        .filter_map(|(entry_idx, entry)| {
+            entry
+                .items
+                .iter()
+                .enumerate()
+                .filter_map(|(item_idx, item)| {
+                    let text = search_text(item)?;
+                    let (score, positions) = matcher.fuzzy_indices(&text, &query)?;
+                    Some(SearchMatch {
+                        entry_idx,
+                        item_idx: Some(item_idx),
+                        positions,
+                        score,
+                    })
+                })
+                .max_by_key(|m| m.score)
+        })
+        .collect::<Vec<_>>();
+
+    // stable sort keeps more recent entries first among equal scores
+    matches.sort_by_key(|m| Reverse(m.score));
+    matches
+}
@@ -0,0 +1,103 @@
+use gpui::{ImageFormat, http_client::Url};
+
+use crate::{
+    models::{ClipboardEntry, ClipboardItem, SourceApp},
+    search::search,
+};
+
+fn text(text: &str) -> ClipboardEntry {
+    ClipboardEntry::new(vec![ClipboardItem::Text(text.to_string())])
+}
+
+fn from_app(mut entry: ClipboardEntry, bundle_id: &str, name: Option<&str>) -> ClipboardEntry {
+    entry.source = Some(SourceApp {
+        bundle_id: bundle_id.to_string(),
+        name: name.map(str::to_string),
+        icon_bytes: None,
+    });
+    entry
+}
+
+/// Indices of the matched entries, best match first
+fn found(entries: &[ClipboardEntry], query: &str) -> Vec<usize> {
+    search(entries, query)
+        .into_iter()
+        .map(|m| m.entry_idx)
+        .collect()
+}
+
+#[test]
+fn empty_query_lists_everything_in_history_order() {
+    let entries = [text("b"), text("a")];
+    assert_eq!(found(&entries, ""), [0, 1]);
+    assert_eq!(found(&entries, "   "), [0, 1]);
+}
+
+#[test]
+fn closer_matches_rank_first() {
+    let entries = [
+        text("cargo build --release"),
+        text("nothing to see"),
+        text("cbr"),
+        text("cargo bench --release"),
+    ];
+    // a contiguous match beats one spread over the text, unrelated entries are dropped
+    assert_eq!(found(&entries, "cbr"), [2, 0, 3]);
+    assert_eq!(found(&entries, "bench"), [3]);
+}
+
+#[test]
+fn equal_scores_keep_newer_entries_first() {
+    let entries = [text("hello"), text("hello")];
+    assert_eq!(found(&entries, "hello"), [0, 1]);
+}
+
+#[test]
+fn matches_urls_file_names_and_the_best_item() {
+    let entries = [
+        ClipboardEntry::new(vec![ClipboardItem::Url(
+            Url::parse("https://example.com/docs").unwrap(),
+        )]),
+        ClipboardEntry::new(vec![
+            ClipboardItem::File {
+                path: "/home/me/report.pdf".into(),
+                icon_bytes: None,
+            },
+            ClipboardItem::File {
+                path: "/home/me/notes.txt".into(),
+                icon_bytes: None,
+            },
+        ]),
+        ClipboardEntry::new(vec![ClipboardItem::image(vec![1], ImageFormat::Png)]),
+    ];
+    assert_eq!(found(&entries, "example"), [0]);
+    // only the file name counts, not the directories it's in
+    assert!(found(&entries, "home").is_empty());
+
+    let matches = search(&entries, "notes");
+    assert_eq!(matches.len(), 1);
+    assert_eq!(matches[0].item_idx, Some(1));
+    assert_eq!(matches[0].positions, [0, 1, 2, 3, 4]);
+}
+
+#[test]
+fn app_filter_keeps_entries_from_matching_apps() {
+    let entries = [
+        from_app(text("one"), "com.apple.Safari", Some("Safari")),
+        from_app(text("two"), "com.microsoft.VSCode", Some("Code")),
+        text("three"),
+        from_app(text("four"), "org.mozilla.firefox", None),
+    ];
+    // by name or bundle id, ignoring case
+    assert_eq!(found(&entries, "app:safari"), [0]);
+    assert_eq!(found(&entries, "app:VSCODE"), [1]);
+    assert_eq!(found(&entries, "app:mozilla"), [3]);
+    // entries without a known source never match a filter
+    assert_eq!(found(&entries, "app:com"), [0, 1]);
+    // every filter has to match
+    assert_eq!(found(&entries, "app:com app:code"), [1]);
+    // combined with a query, and an empty filter is ignored
+    assert_eq!(found(&entries, "app:com o"), [0, 1]);
+    assert_eq!(found(&entries, "app: three"), [2]);
+    assert!(found(&entries, "app:safari two").is_empty());
+}
@@ -0,0 +1,178 @@
+use std::{
+    fmt, fs, io,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use gpui::BackgroundExecutor;
+
+use crate::models::{ClipboardEntry, add_thumbnails};
+
+mod file;
+mod sqlite;
+#[cfg(test)]
+mod tests;
+
+pub use file::FileStorage;
+pub use sqlite::SqliteStorage;
+
+/// Persists clipboard history between runs
+pub trait Storage: Send {
+    fn load(&mut self) -> Result<Vec<ClipboardEntry>, StorageError>;
+
+    /// Replaces the stored history with the `persistent` ones of `entries`, newest first
+    fn save(&mut self, entries: &[ClipboardEntry]) -> Result<(), StorageError>;
+}
+
+/// Entries that may be written to disk, those due to expire are only ever kept in memory
+fn persistent(entries: &[ClipboardEntry]) -> impl Iterator<Item = &ClipboardEntry> {
+    entries.iter().filter(|entry| entry.expires_at.is_none())
+}
+
+/// The history's storage, shared by everything that changes history. Saves are written by one
+/// writer at a time so an older snapshot can never overwrite a newer one.
+#[derive(Clone)]
+pub struct Store(Arc<StoreInner>);
+
+struct StoreInner {
+    storage: Mutex<Box<dyn Storage>>,
+    pending: Mutex<PendingSave>,
+}
+
+#[derive(Default)]
+struct PendingSave {
+    /// Latest snapshot not yet handed to the writer
+    entries: Option<Vec<ClipboardEntry>>,
+    /// Whether a writer is running, it picks up `entries` before it finishes
+    writing: bool,
+}
+
+impl Store {
+    pub fn new(storage: impl Storage + 'static) -> Self {
+        Self(Arc::new(StoreInner {
+            storage: Mutex::new(Box::new(storage)),
+            pending: Mutex::default(),
+        }))
+    }
+
+    pub fn load(&self) -> Result<Vec<ClipboardEntry>, StorageError> {
+        self.0.storage.lock().unwrap().load()
+    }
+}
+
+/// Saves `entries` off the main thread so large histories don't stall the ui. Only the latest
+/// snapshot is written when saves come in faster than they finish.
+pub fn save_in_background(
+    executor: &BackgroundExecutor,
+    store: Store,
+    entries: Vec<ClipboardEntry>,
+) {
+    let mut pending = store.0.pending.lock().unwrap();
+    pending.entries = Some(persistent(&entries).cloned().collect());
+    if pending.writing {
+        return;
+    }
+    pending.writing = true;
+    drop(pending);
+
+    executor
+        .spawn(async move {
+            loop {
+                let entries = {
+                    let mut pending = store.0.pending.lock().unwrap();
+                    match pending.entries.take() {
+                        Some(entries) => entries,
+                        None => {
+                            pending.writing = false;
+                            break;
+                        }
+                    }
+                };
+                if let Err(err) = store.0.storage.lock().unwrap().save(&entries) {
+                    eprintln!("failed to save history: {err}");
+                }
+            }
+        })
+        .detach();
+}
+
+#[derive(Debug)]
+pub enum StorageError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    Sqlite(rusqlite::Error),
+    /// The database was written by a newer version of stele
+    UnsupportedVersion(u32),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Io(err) => write!(f, "io error: {err}"),
+            StorageError::Json(err) => write!(f, "serialization error: {err}"),
+            StorageError::Sqlite(err) => write!(f, "database error: {err}"),
+            StorageError::UnsupportedVersion(version) => {
+                write!(f, "unsupported schema version {version}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<io::Error> for StorageError {
+    fn from(err: io::Error) -> Self {
+        StorageError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for StorageError {
+    fn from(err: serde_json::Error) -> Self {
+        StorageError::Json(err)
+    }
+}
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(err: rusqlite::Error) -> Self {
+        StorageError::Sqlite(err)
+    }
+}
+
+/// Directory stele keeps its persistent data in, e.g. `~/Library/Application Support/stele`
+pub fn data_dir() -> PathBuf {
+    dirs::data_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("stele")
+}
+
+/// Opens the history database in the data directory, importing an older json history if present.
+/// Falls back to an in-memory database so a broken data directory never stops the app from starting.
+pub fn open_default_store() -> Store {
+    let dir = data_dir();
+    let is_new = !dir.join(SqliteStorage::FILE_NAME).exists();
+
+    let mut storage = match SqliteStorage::open(&dir) {
+        Ok(storage) => storage,
+        Err(err) => {
+            eprintln!("failed to open history database, history won't be saved: {err}");
+            SqliteStorage::open_in_memory().expect("Failed to open in-memory database")
+        }
+    };
+
+    let json_path = dir.join(FileStorage::FILE_NAME);
+    if is_new && json_path.exists() {
+        match FileStorage::new(&dir).load().and_then(|mut entries| {
+            for entry in &mut entries {
+                add_thumbnails(&mut entry.items);
+            }
+            storage.save(&entries)
+        }) {
+            Ok(()) => {
+                let _ = fs::rename(&json_path, json_path.with_extension("json.imported"));
+            }
+            Err(err) => eprintln!("failed to import json history: {err}"),
+        }
+    }
+
+    Store::new(storage)
+}
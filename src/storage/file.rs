@@ -0,0 +1,123 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    models::ClipboardEntry,
+    storage::{Storage, StorageError, persistent},
+};
+
+/// Stores the whole history as a single json file, replaced atomically on every save
+pub struct FileStorage {
+    path: PathBuf,
+}
+
+#[derive(Deserialize)]
+struct HistoryFile {
+    version: u32,
+    // entries are decoded one at a time so a single bad entry doesn't discard the rest
+    entries: Vec<serde_json::Value>,
+}
+
+#[derive(Serialize)]
+struct HistoryFileRef<'a> {
+    version: u32,
+    entries: Vec<&'a ClipboardEntry>,
+}
+
+impl FileStorage {
+    pub const FILE_NAME: &str = "history.json";
+    const VERSION: u32 = 2;
+
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            path: dir.join(Self::FILE_NAME),
+        }
+    }
+
+    /// Moves an unreadable history file out of the way so it isn't overwritten by the next save
+    fn quarantine(&self, reason: &str) {
+        let corrupt_path = self.path.with_extension("json.corrupt");
+        eprintln!(
+            "history file {} is unreadable ({reason}), moving it to {}",
+            self.path.display(),
+            corrupt_path.display()
+        );
+        if let Err(err) = fs::rename(&self.path, &corrupt_path) {
+            eprintln!("failed to move corrupt history file: {err}");
+        }
+    }
+}
+
+impl Storage for FileStorage {
+    fn load(&mut self) -> Result<Vec<ClipboardEntry>, StorageError> {
+        let bytes = match fs::read(&self.path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let file = match serde_json::from_slice::<HistoryFile>(&bytes) {
+            Ok(file) => file,
+            Err(err) => {
+                self.quarantine(&err.to_string());
+                return Ok(Vec::new());
+            }
+        };
+
+        if file.version > Self::VERSION {
+            self.quarantine(&format!("unsupported version {}", file.version));
+            return Ok(Vec::new());
+        }
+
+        let mut entries: Vec<ClipboardEntry> = file
+            .entries
+            .into_iter()
+            .filter_map(|value| match serde_json::from_value(value) {
+                Ok(entry) => Some(entry),
+                Err(err) => {
+                    eprintln!("skipping unreadable history entry: {err}");
+                    None
+                }
+            })
+            .collect();
+
+        // version 1 files predate entry ids, number them oldest first
+        if file.version < 2 {
+            let len = entries.len() as u64;
+            for (i, entry) in entries.iter_mut().enumerate() {
+                entry.id = len - i as u64;
+            }
+        }
+
+        Ok(entries)
+    }
+
+    fn save(&mut self, entries: &[ClipboardEntry]) -> Result<(), StorageError> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        // write to a temporary file first so a crash mid-write never leaves a truncated history behind
+        let tmp_path = self.path.with_extension("json.tmp");
+        let file = File::create(&tmp_path)?;
+        let mut writer = BufWriter::new(&file);
+        serde_json::to_writer(
+            &mut writer,
+            &HistoryFileRef {
+                version: Self::VERSION,
+                entries: persistent(entries).collect(),
+            },
+        )?;
+        writer.flush()?;
+        drop(writer);
+        file.sync_all()?;
+        fs::rename(&tmp_path, &self.path)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,651 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use chrono::{DateTime, Local, Utc};
+use gpui::{ImageFormat, http_client::Url};
+use rusqlite::{Connection, Transaction, params};
+
+use crate::{
+    models::{
+        Blob, ClipboardEntry, ClipboardItem, RawItem, RawRepresentation, RichTextFormat, SourceApp,
+        thumbnail,
+    },
+    storage::{Storage, StorageError, persistent},
+};
+
+#[cfg(test)]
+mod tests;
+
+/// Schema changes, `MIGRATIONS[n]` upgrades a database from version `n` to `n + 1`.
+/// Existing migrations must never be edited, append a new one instead.
+/// Timestamps are stored as utc so they sort correctly as text.
+const MIGRATIONS: &[&str] = &[
+    "
+    CREATE TABLE entries (
+        id INTEGER PRIMARY KEY,
+        timestamp TEXT NOT NULL
+    );
+    CREATE TABLE items (
+        entry_id INTEGER NOT NULL REFERENCES entries(id) ON DELETE CASCADE,
+        position INTEGER NOT NULL,
+        kind TEXT NOT NULL,
+        text TEXT,
+        data BLOB,
+        format TEXT,
+        PRIMARY KEY (entry_id, position)
+    );
+    CREATE INDEX entries_timestamp ON entries(timestamp);
+",
+    "
+    ALTER TABLE entries ADD COLUMN pinned INTEGER NOT NULL DEFAULT 0;
+",
+    "
+    CREATE TABLE apps (
+        bundle_id TEXT PRIMARY KEY,
+        name TEXT,
+        icon BLOB
+    );
+    ALTER TABLE entries ADD COLUMN source TEXT REFERENCES apps(bundle_id);
+",
+    "
+    CREATE TABLE representations (
+        entry_id INTEGER NOT NULL REFERENCES entries(id) ON DELETE CASCADE,
+        item INTEGER NOT NULL,
+        position INTEGER NOT NULL,
+        type TEXT NOT NULL,
+        data BLOB NOT NULL,
+        PRIMARY KEY (entry_id, item, position)
+    );
+",
+    "
+    ALTER TABLE items ADD COLUMN thumbnail BLOB;
+    ALTER TABLE items ADD COLUMN blob_file TEXT;
+    ALTER TABLE items ADD COLUMN length INTEGER;
+    ALTER TABLE items ADD COLUMN hash INTEGER;
+    CREATE TABLE representations_new (
+        entry_id INTEGER NOT NULL REFERENCES entries(id) ON DELETE CASCADE,
+        item INTEGER NOT NULL,
+        position INTEGER NOT NULL,
+        type TEXT NOT NULL,
+        data BLOB,
+        blob_file TEXT,
+        length INTEGER,
+        hash INTEGER,
+        PRIMARY KEY (entry_id, item, position)
+    );
+    INSERT INTO representations_new (entry_id, item, position, type, data)
+        SELECT entry_id, item, position, type, data FROM representations;
+    DROP TABLE representations;
+    ALTER TABLE representations_new RENAME TO representations;
+",
+    // hashes used to come from std's DefaultHasher, which may change between rust releases.
+    // Those of data in files are recomputed when needed, inline data is hashed as it's loaded.
+    "
+    UPDATE items SET hash = NULL WHERE blob_file IS NOT NULL;
+    UPDATE representations SET hash = NULL WHERE blob_file IS NOT NULL;
+",
+];
+
+/// Data smaller than this stays in the database, anything larger is moved to a file of its own
+const INLINE_BLOB_LIMIT: usize = 16 * 1024;
+
+/// Stores history in a sqlite database, only rows that changed are written on save.
+/// Large images and raw data are kept in files next to it, so loaded history doesn't hold them
+/// in memory.
+pub struct SqliteStorage {
+    conn: Connection,
+    /// Where large data is moved to, `None` keeps everything in the database
+    blob_dir: Option<PathBuf>,
+}
+
+impl SqliteStorage {
+    pub const FILE_NAME: &str = "history.db";
+    const BLOB_DIR: &str = "blobs";
+
+    pub fn open(dir: &Path) -> Result<Self, StorageError> {
+        let blob_dir = dir.join(Self::BLOB_DIR);
+        fs::create_dir_all(&blob_dir)?;
+        let path = dir.join(Self::FILE_NAME);
+        let mut storage =
+            match Self::from_connection(Connection::open(&path)?, Some(blob_dir.clone())) {
+                Err(StorageError::Sqlite(err)) if is_corrupt(&err) => {
+                    let corrupt_path = quarantine(&path, &err.to_string());
+                    eprintln!(
+                        "history database {} is unreadable ({err}), moved it to {}",
+                        path.display(),
+                        corrupt_path.display()
+                    );
+                    Self::from_connection(Connection::open(&path)?, Some(blob_dir))
+                }
+                result => result,
+            }?;
+        storage.move_out_stored_data()?;
+        Ok(storage)
+    }
+
+    pub fn open_in_memory() -> Result<Self, StorageError> {
+        Self::from_connection(Connection::open_in_memory()?, None)
+    }
+
+    fn from_connection(
+        mut conn: Connection,
+        blob_dir: Option<PathBuf>,
+    ) -> Result<Self, StorageError> {
+        conn.pragma_update(None, "foreign_keys", true)?;
+        migrate(&mut conn)?;
+        Ok(Self { conn, blob_dir })
+    }
+
+    /// Gives images and raw data saved before they were kept in files the files and
+    /// thumbnails they'd get now, rows stored since always have a hash or a file
+    fn move_out_stored_data(&mut self) -> Result<(), StorageError> {
+        let tx = self.conn.transaction()?;
+        let images = tx
+            .prepare(
+                "SELECT entry_id, position, data FROM items
+                 WHERE kind = 'image' AND hash IS NULL AND blob_file IS NULL",
+            )?
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, Option<Vec<u8>>>(2)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        for (entry_id, position, data) in images {
+            let Some(data) = data else { continue };
+            let thumbnail = thumbnail(&data);
+            let blob = Blob::new(data);
+            let StoredBlob { file, data } = store_blob(
+                &blob,
+                self.blob_dir.as_deref(),
+                &item_blob_name(entry_id, position),
+            )?;
+            tx.execute(
+                "UPDATE items SET data = ?3, thumbnail = ?4, blob_file = ?5, length = ?6, hash = ?7
+                 WHERE entry_id = ?1 AND position = ?2",
+                params![
+                    entry_id,
+                    position,
+                    data.as_deref(),
+                    thumbnail,
+                    file,
+                    blob.len() as i64,
+                    blob.hash()? as i64
+                ],
+            )?;
+        }
+
+        let representations = tx
+            .prepare(
+                "SELECT entry_id, item, position, data FROM representations
+                 WHERE hash IS NULL AND blob_file IS NULL",
+            )?
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, i64>(2)?,
+                    row.get::<_, Option<Vec<u8>>>(3)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        for (entry_id, item, position, data) in representations {
+            let Some(data) = data else { continue };
+            let blob = Blob::new(data);
+            let StoredBlob { file, data } = store_blob(
+                &blob,
+                self.blob_dir.as_deref(),
+                &representation_blob_name(entry_id, item, position),
+            )?;
+            tx.execute(
+                "UPDATE representations SET data = ?4, blob_file = ?5, length = ?6, hash = ?7
+                 WHERE entry_id = ?1 AND item = ?2 AND position = ?3",
+                params![
+                    entry_id,
+                    item,
+                    position,
+                    data.as_deref(),
+                    file,
+                    blob.len() as i64,
+                    blob.hash()? as i64
+                ],
+            )?;
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+fn item_blob_name(entry_id: i64, position: i64) -> String {
+    format!("{entry_id}-{position}")
+}
+
+fn representation_blob_name(entry_id: i64, item: i64, position: i64) -> String {
+    format!("{entry_id}-raw-{item}-{position}")
+}
+
+/// Where a row's blob went, one of the two is set
+struct StoredBlob {
+    /// Name of the file in the blob directory holding the data
+    file: Option<String>,
+    /// The data itself, when it stays in the database
+    data: Option<Arc<Vec<u8>>>,
+}
+
+/// Moves large blobs to a file called `name` in `blob_dir`, smaller ones stay in the row
+fn store_blob(
+    blob: &Blob,
+    blob_dir: Option<&Path>,
+    name: &str,
+) -> Result<StoredBlob, StorageError> {
+    match blob_dir {
+        Some(dir) if blob.len() >= INLINE_BLOB_LIMIT => {
+            let path = blob.move_to_file(&dir.join(name))?;
+            let file = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned());
+            Ok(StoredBlob { file, data: None })
+        }
+        _ => Ok(StoredBlob {
+            file: None,
+            data: Some(blob.load()?),
+        }),
+    }
+}
+
+/// The blob stored in a row, either in `file` within `blob_dir` or inline as `data`
+fn read_blob(
+    blob_dir: Option<&Path>,
+    file: Option<String>,
+    data: Option<Vec<u8>>,
+    length: Option<i64>,
+    hash: Option<i64>,
+) -> Option<Blob> {
+    match (file, data) {
+        (Some(file), _) => Some(Blob::in_file(
+            blob_dir?.join(file),
+            length? as usize,
+            hash.map(|hash| hash as u64),
+        )),
+        (None, Some(data)) => Some(Blob::new(data)),
+        (None, None) => None,
+    }
+}
+
+fn is_corrupt(err: &rusqlite::Error) -> bool {
+    matches!(
+        err.sqlite_error_code(),
+        Some(rusqlite::ErrorCode::NotADatabase | rusqlite::ErrorCode::DatabaseCorrupt)
+    )
+}
+
+fn quarantine(path: &Path, reason: &str) -> PathBuf {
+    let corrupt_path = path.with_extension("db.corrupt");
+    if let Err(err) = fs::rename(path, &corrupt_path) {
+        eprintln!("failed to move corrupt database ({reason}): {err}");
+    }
+    corrupt_path
+}
+
+fn schema_version(conn: &Connection) -> Result<u32, StorageError> {
+    Ok(conn.pragma_query_value(None, "user_version", |row| row.get(0))?)
+}
+
+/// Brings the schema up to the latest version, each step runs in its own transaction
+fn migrate(conn: &mut Connection) -> Result<(), StorageError> {
+    let latest = MIGRATIONS.len() as u32;
+    let mut version = schema_version(conn)?;
+    if version > latest {
+        return Err(StorageError::UnsupportedVersion(version));
+    }
+
+    while version < latest {
+        let tx = conn.transaction()?;
+        tx.execute_batch(MIGRATIONS[version as usize])?;
+        tx.pragma_update(None, "user_version", version + 1)?;
+        tx.commit()?;
+        version += 1;
+    }
+
+    Ok(())
+}
+
+/// Stores the app so entries can reference it, apps are shared to avoid duplicating icons
+fn upsert_app(tx: &Transaction, app: &SourceApp) -> Result<(), StorageError> {
+    tx.prepare_cached(
+        "INSERT INTO apps (bundle_id, name, icon) VALUES (?1, ?2, ?3)
+         ON CONFLICT(bundle_id) DO UPDATE SET name = excluded.name, icon = excluded.icon",
+    )?
+    .execute(params![app.bundle_id, app.name, app.icon_bytes])?;
+    Ok(())
+}
+
+fn insert_entry(
+    tx: &Transaction,
+    entry: &ClipboardEntry,
+    blob_dir: Option<&Path>,
+) -> Result<(), StorageError> {
+    if let Some(app) = &entry.source {
+        upsert_app(tx, app)?;
+    }
+    tx.execute(
+        "INSERT INTO entries (id, timestamp, pinned, source) VALUES (?1, ?2, ?3, ?4)",
+        params![
+            entry.id as i64,
+            entry.timestamp.with_timezone(&Utc),
+            entry.pinned,
+            entry.source.as_ref().map(|app| &app.bundle_id)
+        ],
+    )?;
+
+    let mut insert_item = tx.prepare_cached(
+        "INSERT INTO items (entry_id, position, kind, text, data, format, thumbnail, blob_file, length, hash)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+    )?;
+    for (position, item) in entry.items.iter().enumerate() {
+        // images are stored first so the row can point at the file holding them
+        let (blob, stored) = match item {
+            ClipboardItem::Image { bytes, .. } => {
+                let name = item_blob_name(entry.id as i64, position as i64);
+                (Some(bytes), Some(store_blob(bytes, blob_dir, &name)?))
+            }
+            _ => (None, None),
+        };
+        let (kind, text, data, format, thumbnail) = match item {
+            ClipboardItem::Text(text) => ("text", Some(text.clone()), None, None, None),
+            ClipboardItem::Url(url) => ("url", Some(url.to_string()), None, None, None),
+            ClipboardItem::File { path, icon_bytes } => (
+                "file",
+                Some(path.to_string_lossy().into_owned()),
+                icon_bytes.as_deref(),
+                None,
+                None,
+            ),
+            ClipboardItem::Image {
+                format, thumbnail, ..
+            } => (
+                "image",
+                None,
+                stored
+                    .as_ref()
+                    .and_then(|stored| stored.data.as_deref())
+                    .map(Vec::as_slice),
+                Some(format.mime_type()),
+                thumbnail.as_deref(),
+            ),
+            ClipboardItem::RichText { text, data, format } => (
+                "rich_text",
+                Some(text.clone()),
+                Some(data.as_slice()),
+                Some(format.mime_type()),
+                None,
+            ),
+        };
+        insert_item.execute(params![
+            entry.id as i64,
+            position as i64,
+            kind,
+            text,
+            data,
+            format,
+            thumbnail,
+            stored.as_ref().and_then(|stored| stored.file.as_deref()),
+            blob.map(|blob| blob.len() as i64),
+            blob.map(Blob::hash).transpose()?.map(|hash| hash as i64)
+        ])?;
+    }
+
+    let mut insert_representation = tx.prepare_cached(
+        "INSERT INTO representations (entry_id, item, position, type, data, blob_file, length, hash)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+    )?;
+    for (item, raw) in entry.raw.iter().enumerate() {
+        for (position, representation) in raw.representations.iter().enumerate() {
+            let name = representation_blob_name(entry.id as i64, item as i64, position as i64);
+            let StoredBlob { file, data } = store_blob(&representation.data, blob_dir, &name)?;
+            insert_representation.execute(params![
+                entry.id as i64,
+                item as i64,
+                position as i64,
+                representation.kind,
+                data.as_deref(),
+                file,
+                representation.data.len() as i64,
+                representation.data.hash()? as i64
+            ])?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Groups representation rows, ordered by item, back into raw items
+fn read_raw(rows: Vec<(i64, RawRepresentation)>) -> Vec<RawItem> {
+    let mut raw: Vec<RawItem> = Vec::new();
+    let mut last_item = None;
+    for (item, representation) in rows {
+        if last_item != Some(item) {
+            raw.push(RawItem {
+                representations: Vec::new(),
+            });
+            last_item = Some(item);
+        }
+        raw.last_mut().unwrap().representations.push(representation);
+    }
+    raw
+}
+
+/// An `items` row
+struct ItemRow {
+    kind: String,
+    text: Option<String>,
+    data: Option<Vec<u8>>,
+    format: Option<String>,
+    thumbnail: Option<Vec<u8>>,
+    blob_file: Option<String>,
+    length: Option<i64>,
+    hash: Option<i64>,
+}
+
+fn read_item(row: ItemRow, blob_dir: Option<&Path>) -> Option<ClipboardItem> {
+    let ItemRow {
+        kind,
+        text,
+        data,
+        format,
+        thumbnail,
+        blob_file,
+        length,
+        hash,
+    } = row;
+    match kind.as_str() {
+        "text" => Some(ClipboardItem::Text(text?)),
+        "url" => Url::parse(&text?).ok().map(ClipboardItem::Url),
+        "file" => Some(ClipboardItem::File {
+            path: PathBuf::from(text?),
+            icon_bytes: data,
+        }),
+        "image" => Some(ClipboardItem::Image {
+            bytes: read_blob(blob_dir, blob_file, data, length, hash)?,
+            format: ImageFormat::from_mime_type(&format?)?,
+            thumbnail,
+        }),
+        "rich_text" => Some(ClipboardItem::RichText {
+            text: text?,
+            data: data?,
+            format: RichTextFormat::from_mime_type(&format?)?,
+        }),
+        _ => None,
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn load(&mut self) -> Result<Vec<ClipboardEntry>, StorageError> {
+        let mut select_entries = self.conn.prepare(
+            "SELECT entries.id, entries.timestamp, entries.pinned, apps.bundle_id, apps.name, apps.icon
+             FROM entries LEFT JOIN apps ON apps.bundle_id = entries.source
+             ORDER BY entries.timestamp DESC",
+        )?;
+        let mut select_items = self.conn.prepare(
+            "SELECT kind, text, data, format, thumbnail, blob_file, length, hash
+             FROM items WHERE entry_id = ?1 ORDER BY position",
+        )?;
+        let mut select_representations = self.conn.prepare(
+            "SELECT item, type, data, blob_file, length, hash FROM representations
+             WHERE entry_id = ?1 ORDER BY item, position",
+        )?;
+        let blob_dir = self.blob_dir.as_deref();
+
+        let rows = select_entries
+            .query_map([], |row| {
+                let source = row
+                    .get::<_, Option<String>>(3)?
+                    .map(|bundle_id| -> rusqlite::Result<SourceApp> {
+                        Ok(SourceApp {
+                            bundle_id,
+                            name: row.get(4)?,
+                            icon_bytes: row.get(5)?,
+                        })
+                    })
+                    .transpose()?;
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, DateTime<Local>>(1)?,
+                    row.get::<_, bool>(2)?,
+                    source,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut entries = Vec::with_capacity(rows.len());
+        for (id, timestamp, pinned, source) in rows {
+            let items = select_items
+                .query_map([id], |row| {
+                    Ok(ItemRow {
+                        kind: row.get(0)?,
+                        text: row.get(1)?,
+                        data: row.get(2)?,
+                        format: row.get(3)?,
+                        thumbnail: row.get(4)?,
+                        blob_file: row.get(5)?,
+                        length: row.get(6)?,
+                        hash: row.get(7)?,
+                    })
+                })?
+                .filter_map(|row| match row {
+                    Ok(row) => {
+                        let kind = row.kind.clone();
+                        let item = read_item(row, blob_dir);
+                        if item.is_none() {
+                            eprintln!("skipping unreadable {kind} item in entry {id}");
+                        }
+                        item
+                    }
+                    Err(err) => {
+                        eprintln!("skipping unreadable item in entry {id}: {err}");
+                        None
+                    }
+                })
+                .collect();
+            let representations = select_representations
+                .query_map([id], |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, String>(1)?,
+                        read_blob(blob_dir, row.get(3)?, row.get(2)?, row.get(4)?, row.get(5)?),
+                    ))
+                })?
+                .filter_map(|row| match row {
+                    Ok((item, kind, Some(data))) => {
+                        Some(Ok((item, RawRepresentation { kind, data })))
+                    }
+                    Ok((_, kind, None)) => {
+                        eprintln!("skipping unreadable {kind} data in entry {id}");
+                        None
+                    }
+                    Err(err) => Some(Err(err)),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            entries.push(ClipboardEntry {
+                id: id as u64,
+                timestamp,
+                items,
+                raw: read_raw(representations),
+                pinned,
+                expires_at: None,
+                source,
+            });
+        }
+
+        Ok(entries)
+    }
+
+    fn save(&mut self, entries: &[ClipboardEntry]) -> Result<(), StorageError> {
+        // expiring entries an older build stored are removed like any other
+        let entries = persistent(entries).collect::<Vec<_>>();
+        let tx = self.conn.transaction()?;
+
+        let stored = tx
+            .prepare("SELECT id FROM entries")?
+            .query_map([], |row| row.get::<_, i64>(0))?
+            .collect::<Result<HashSet<_>, _>>()?;
+        let current = entries
+            .iter()
+            .map(|entry| entry.id as i64)
+            .collect::<HashSet<_>>();
+
+        let mut removed_files = Vec::new();
+        for id in stored.difference(&current) {
+            removed_files.extend(
+                tx.prepare_cached(
+                    "SELECT blob_file FROM items WHERE entry_id = ?1 AND blob_file IS NOT NULL
+                     UNION ALL
+                     SELECT blob_file FROM representations WHERE entry_id = ?1 AND blob_file IS NOT NULL",
+                )?
+                .query_map([id], |row| row.get::<_, String>(0))?
+                .collect::<Result<Vec<_>, _>>()?,
+            );
+            tx.execute("DELETE FROM entries WHERE id = ?1", [id])?;
+        }
+
+        // an entry's items never change once captured, so existing rows only need their metadata updated
+        for entry in entries {
+            if stored.contains(&(entry.id as i64)) {
+                if let Some(app) = &entry.source {
+                    upsert_app(&tx, app)?;
+                }
+                tx.execute(
+                    "UPDATE entries SET timestamp = ?2, pinned = ?3, source = ?4 WHERE id = ?1",
+                    params![
+                        entry.id as i64,
+                        entry.timestamp.with_timezone(&Utc),
+                        entry.pinned,
+                        entry.source.as_ref().map(|app| &app.bundle_id)
+                    ],
+                )?;
+            } else {
+                insert_entry(&tx, entry, self.blob_dir.as_deref())?;
+            }
+        }
+
+        tx.commit()?;
+
+        // only once the rows are gone, so a failed save never leaves rows pointing at missing files
+        if let Some(dir) = &self.blob_dir {
+            for file in removed_files {
+                if let Err(err) = fs::remove_file(dir.join(&file)) {
+                    eprintln!("failed to remove {file}: {err}");
+                }
+            }
+        }
+        Ok(())
+    }
+}
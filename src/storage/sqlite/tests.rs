@@ -0,0 +1,300 @@
+use std::{
+    env, fs,
+    io::Cursor,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use chrono::{Local, TimeZone, Utc};
+use gpui::{ImageFormat, http_client::Url};
+use rusqlite::{Connection, params};
+
+use crate::{
+    models::{
+        Blob, ClipboardEntry, ClipboardItem, RawItem, RawRepresentation, RichTextFormat, SourceApp,
+    },
+    storage::{
+        SqliteStorage, Storage, StorageError,
+        sqlite::{INLINE_BLOB_LIMIT, MIGRATIONS, schema_version},
+    },
+};
+
+/// A fresh directory for a test's database
+fn temp_dir() -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let dir = env::temp_dir().join(format!(
+        "stele-sqlite-{}-{}",
+        process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// A png that doesn't compress, so it's large enough to be moved to a file
+fn noisy_png() -> Vec<u8> {
+    let mut seed = 1u32;
+    let image = image::RgbaImage::from_fn(100, 100, |_, _| {
+        seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+        image::Rgba(seed.to_le_bytes())
+    });
+    let mut png = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+        .unwrap();
+    assert!(png.len() >= INLINE_BLOB_LIMIT);
+    png
+}
+
+/// A database as a build at schema `version` left it: a text entry and an older image entry,
+/// with whatever else that version could store
+fn old_database(dir: &Path, version: usize, png: &[u8], raw: &[u8]) {
+    let conn = Connection::open(dir.join(SqliteStorage::FILE_NAME)).unwrap();
+    for migration in &MIGRATIONS[..version] {
+        conn.execute_batch(migration).unwrap();
+    }
+    conn.pragma_update(None, "user_version", version as u32)
+        .unwrap();
+    if version == 0 {
+        return;
+    }
+
+    conn.execute(
+        "INSERT INTO entries (id, timestamp) VALUES (1, ?1), (2, ?2)",
+        params![
+            Utc.timestamp_opt(2_000, 0).unwrap(),
+            Utc.timestamp_opt(1_000, 0).unwrap()
+        ],
+    )
+    .unwrap();
+    conn.execute(
+        "INSERT INTO items (entry_id, position, kind, text, data, format)
+         VALUES (1, 0, 'text', 'hello', NULL, NULL), (2, 0, 'image', NULL, ?1, 'image/png')",
+        [png],
+    )
+    .unwrap();
+    if version >= 2 {
+        conn.execute("UPDATE entries SET pinned = 1 WHERE id = 2", [])
+            .unwrap();
+    }
+    if version >= 3 {
+        conn.execute_batch(
+            "INSERT INTO apps (bundle_id, name) VALUES ('com.apple.TextEdit', 'TextEdit');
+             UPDATE entries SET source = 'com.apple.TextEdit' WHERE id = 1;",
+        )
+        .unwrap();
+    }
+    if version >= 4 {
+        conn.execute(
+            "INSERT INTO representations (entry_id, item, position, type, data)
+             VALUES (1, 0, 0, 'public.utf8-plain-text', ?1)",
+            [raw],
+        )
+        .unwrap();
+    }
+    if version >= 5 {
+        // moved to a file, with a hash from the std hasher builds used to store
+        let blob_dir = dir.join(SqliteStorage::BLOB_DIR);
+        fs::create_dir_all(&blob_dir).unwrap();
+        fs::write(blob_dir.join("2-0"), png).unwrap();
+        conn.execute(
+            "UPDATE items SET data = NULL, thumbnail = ?1, blob_file = '2-0', length = ?2, hash = 1
+             WHERE entry_id = 2",
+            params![png, png.len() as i64],
+        )
+        .unwrap();
+    }
+}
+
+fn columns(storage: &SqliteStorage, table: &str) -> Vec<String> {
+    storage
+        .conn
+        .prepare(&format!("SELECT name FROM pragma_table_info('{table}')"))
+        .unwrap()
+        .query_map([], |row| row.get(0))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap()
+}
+
+#[test]
+fn upgrades_databases_from_every_older_version() {
+    let png = noisy_png();
+    let raw = vec![7; INLINE_BLOB_LIMIT];
+    for version in 0..MIGRATIONS.len() {
+        let dir = temp_dir();
+        old_database(&dir, version, &png, &raw);
+
+        let mut storage = SqliteStorage::open(&dir).unwrap();
+        assert_eq!(
+            schema_version(&storage.conn).unwrap(),
+            MIGRATIONS.len() as u32
+        );
+        for column in ["thumbnail", "blob_file", "length", "hash"] {
+            assert!(
+                columns(&storage, "items").contains(&column.to_string()),
+                "items.{column} missing after upgrading from version {version}"
+            );
+        }
+        assert!(columns(&storage, "entries").contains(&"source".to_string()));
+
+        let entries = storage.load().unwrap();
+        if version == 0 {
+            assert!(entries.is_empty());
+            continue;
+        }
+        assert_eq!(
+            entries.iter().map(|entry| entry.id).collect::<Vec<_>>(),
+            [1, 2]
+        );
+        assert_eq!(entries[0].timestamp.timestamp(), 2_000);
+        assert!(matches!(&entries[0].items[..], [ClipboardItem::Text(text)] if text == "hello"));
+        assert_eq!(entries[1].pinned, version >= 2);
+        assert_eq!(
+            entries[0]
+                .source
+                .as_ref()
+                .map(|app| app.display_name().to_string()),
+            (version >= 3).then(|| "TextEdit".to_string())
+        );
+
+        // images stored before blob files are moved out and get a thumbnail
+        let [
+            ClipboardItem::Image {
+                bytes, thumbnail, ..
+            },
+        ] = &entries[1].items[..]
+        else {
+            panic!("expected the image to survive upgrading from version {version}");
+        };
+        assert_eq!(bytes.load().unwrap().as_slice(), png.as_slice());
+        assert_eq!(
+            bytes.hash().unwrap(),
+            Blob::new(png.clone()).hash().unwrap()
+        );
+        assert!(thumbnail.is_some());
+        assert!(dir.join(SqliteStorage::BLOB_DIR).join("2-0").exists());
+
+        if version >= 4 {
+            let [raw_item] = &entries[0].raw[..] else {
+                panic!("expected the raw item to survive upgrading from version {version}");
+            };
+            assert_eq!(raw_item.representations[0].kind, "public.utf8-plain-text");
+            assert_eq!(
+                raw_item.representations[0].data.load().unwrap().as_slice(),
+                raw.as_slice()
+            );
+            assert!(dir.join(SqliteStorage::BLOB_DIR).join("1-raw-0-0").exists());
+        } else {
+            assert!(entries[0].raw.is_empty());
+        }
+    }
+}
+
+#[test]
+fn refuses_databases_from_a_newer_version() {
+    let dir = temp_dir();
+    let conn = Connection::open(dir.join(SqliteStorage::FILE_NAME)).unwrap();
+    conn.pragma_update(None, "user_version", MIGRATIONS.len() as u32 + 1)
+        .unwrap();
+    drop(conn);
+
+    assert!(matches!(
+        SqliteStorage::open(&dir),
+        Err(StorageError::UnsupportedVersion(version)) if version == MIGRATIONS.len() as u32 + 1
+    ));
+}
+
+#[test]
+fn saved_history_loads_back_the_same() {
+    let dir = temp_dir();
+    let png = noisy_png();
+
+    let mut text = ClipboardEntry::new(vec![ClipboardItem::Text("hello".to_string())]);
+    text.pinned = true;
+    text.source = Some(SourceApp {
+        bundle_id: "com.apple.TextEdit".to_string(),
+        name: Some("TextEdit".to_string()),
+        icon_bytes: Some(vec![1, 2, 3]),
+    });
+    let mut rich = ClipboardEntry::new(vec![
+        ClipboardItem::RichText {
+            text: "bold".to_string(),
+            data: b"{\\rtf1 \\b bold}".to_vec(),
+            format: RichTextFormat::Rtf,
+        },
+        ClipboardItem::Url(Url::parse("https://example.com/a").unwrap()),
+        ClipboardItem::File {
+            path: "/tmp/a.txt".into(),
+            icon_bytes: None,
+        },
+    ]);
+    rich.timestamp = text.timestamp - chrono::Duration::seconds(5);
+    rich.raw = vec![RawItem {
+        representations: vec![
+            RawRepresentation {
+                kind: "public.rtf".to_string(),
+                data: Blob::new(b"{\\rtf1 \\b bold}".to_vec()),
+            },
+            RawRepresentation {
+                kind: "com.example.large".to_string(),
+                data: Blob::new(vec![7; INLINE_BLOB_LIMIT]),
+            },
+        ],
+    }];
+    let mut image = ClipboardEntry::new(vec![ClipboardItem::Image {
+        bytes: Blob::new(png.clone()),
+        format: ImageFormat::Png,
+        thumbnail: Some(vec![4, 5, 6]),
+    }]);
+    image.timestamp = text.timestamp - chrono::Duration::seconds(10);
+    let entries = vec![text, rich, image];
+
+    SqliteStorage::open(&dir).unwrap().save(&entries).unwrap();
+    let mut storage = SqliteStorage::open(&dir).unwrap();
+    let loaded = storage.load().unwrap();
+
+    assert_eq!(loaded.len(), entries.len());
+    for (loaded, saved) in loaded.iter().zip(&entries) {
+        assert_eq!(loaded.id, saved.id);
+        assert_eq!(loaded.timestamp, saved.timestamp);
+        assert_eq!(loaded.pinned, saved.pinned);
+        assert!(loaded.items == saved.items);
+        assert!(loaded.raw == saved.raw);
+        assert!(loaded.source == saved.source);
+    }
+    let image_file = dir
+        .join(SqliteStorage::BLOB_DIR)
+        .join(format!("{}-0", entries[2].id));
+    assert_eq!(fs::read(&image_file).unwrap(), png);
+
+    // removing an entry removes the files holding its data
+    storage.save(&loaded[..2]).unwrap();
+    assert_eq!(storage.load().unwrap().len(), 2);
+    assert!(!image_file.exists());
+}
+
+#[test]
+fn expiring_entries_are_never_stored() {
+    let dir = temp_dir();
+    let mut storage = SqliteStorage::open(&dir).unwrap();
+    let mut entry = ClipboardEntry::new(vec![ClipboardItem::Image {
+        bytes: Blob::new(noisy_png()),
+        format: ImageFormat::Png,
+        thumbnail: None,
+    }]);
+    let file = dir
+        .join(SqliteStorage::BLOB_DIR)
+        .join(format!("{}-0", entry.id));
+
+    // stored by a build that didn't hold expiring entries back
+    storage.save(std::slice::from_ref(&entry)).unwrap();
+    assert!(file.exists());
+
+    entry.expires_at = Some(Local::now() + chrono::Duration::seconds(60));
+    storage.save(std::slice::from_ref(&entry)).unwrap();
+    assert!(storage.load().unwrap().is_empty());
+    assert!(!file.exists());
+}
@@ -0,0 +1,53 @@
+use std::sync::{Arc, Mutex};
+
+use gpui::TestAppContext;
+
+use crate::{
+    models::{ClipboardEntry, ClipboardItem},
+    storage::{Storage, StorageError, Store, save_in_background},
+};
+
+/// Storage that keeps the ids of every snapshot it's asked to save
+struct RecordingStorage {
+    saves: Arc<Mutex<Vec<Vec<u64>>>>,
+}
+
+impl Storage for RecordingStorage {
+    fn load(&mut self) -> Result<Vec<ClipboardEntry>, StorageError> {
+        Ok(Vec::new())
+    }
+
+    fn save(&mut self, entries: &[ClipboardEntry]) -> Result<(), StorageError> {
+        let ids = entries.iter().map(|entry| entry.id).collect();
+        self.saves.lock().unwrap().push(ids);
+        Ok(())
+    }
+}
+
+#[test]
+fn only_the_latest_pending_snapshot_is_written() {
+    let mut cx = TestAppContext::single();
+    let saves = Arc::default();
+    let store = Store::new(RecordingStorage {
+        saves: Arc::clone(&saves),
+    });
+
+    let mut entries = Vec::new();
+    for text in ["a", "b", "c"] {
+        entries.insert(
+            0,
+            ClipboardEntry::new(vec![ClipboardItem::Text(text.into())]),
+        );
+        save_in_background(&cx.executor(), store.clone(), entries.clone());
+    }
+    cx.run_until_parked();
+    let ids = entries.iter().map(|entry| entry.id).collect::<Vec<_>>();
+    assert_eq!(saves.lock().unwrap().len(), 1);
+    assert_eq!(saves.lock().unwrap()[0], ids);
+
+    // the writer is done, so a later save starts a new one
+    entries.pop();
+    save_in_background(&cx.executor(), store.clone(), entries.clone());
+    cx.run_until_parked();
+    assert_eq!(saves.lock().unwrap().last().unwrap(), &ids[..2]);
+}
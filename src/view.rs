@@ -1,156 +1,370 @@
-use dispatch2::run_on_main;
 use gpui::{
-    App, Context, CursorStyle, FocusHandle, Image, ImageFormat, ImageSource, InteractiveElement,
-    IntoElement, KeyDownEvent, ObjectFit, Overflow, ParentElement, Render, ScrollHandle,
-    StatefulInteractiveElement, Styled, StyledImage, Window, div, hsla, img,
-    prelude::FluentBuilder, px, svg, uniform_list,
-};
-use objc2_app_kit::{
-    NSPasteboard, NSPasteboardTypeFileURL, NSPasteboardTypePNG, NSPasteboardTypeString,
-    NSPasteboardTypeTIFF, NSPasteboardTypeURL, NSWorkspace,
-};
-use objc2_foundation::{NSData, NSString};
-use std::{
-    ops::Range,
-    path::{Path, PathBuf},
-    sync::Arc,
+    App, Context, CursorStyle, FocusHandle, FontWeight, HighlightStyle, Image, ImageFormat,
+    InteractiveElement, IntoElement, KeyDownEvent, ObjectFit, ParentElement, Render, ScrollHandle,
+    StatefulInteractiveElement, Styled, StyledImage, StyledText, Window, div, hsla, img,
+    prelude::FluentBuilder, px,
 };
+use std::{collections::HashSet, ops::Range, path::Path, sync::Arc};
 
 use crate::{
-    models::{ClipboardEntry, ClipboardItem, History},
-    monitor::{NSPASTEBOARD_TYPE_GIF, NSPASTEBOARD_TYPE_JPEG},
+    backend::Backend,
+    config::Config,
+    models::{ClipboardEntry, ClipboardItem, History, RichTextFormat, SourceApp, history_size},
+    paste_stack::{PasteStack, set_stack_order, toggle_paste_stack},
+    search::{SearchMatch, search, search_text},
+    storage::{Store, save_in_background},
 };
 
+#[cfg(test)]
+mod tests;
+
 pub struct View {
+    history: History,
+    store: Store,
     snapshot: Vec<ClipboardEntry>,
+    matches: Vec<SearchMatch>,
+    query: String,
+    searching: bool,
     cur_idx: usize,
     focus_handle: FocusHandle,
     scroll_handle: ScrollHandle,
+    backend: Backend,
+    /// Entries whose files are listed one by one rather than as "N files"
+    expanded: HashSet<u64>,
+    /// Bytes held by the history, shown against the configured budget
+    history_size: usize,
 }
 
-fn render_item(item: ClipboardItem) -> impl IntoElement {
-    match item {
-        ClipboardItem::Text(text) => {
-            let text = if text.len() > 25 {
-                format!("{}...", &text[0..25])
-            } else {
-                text.clone()
-            };
+const MAX_DISPLAY_CHARS: usize = 25;
 
-            div().child(text)
-        }
-        ClipboardItem::Url(url) => {
-            let url_string = url.to_string();
-            let url_string = if url_string.len() > 25 {
-                format!("{}...", &url_string[0..25])
-            } else {
-                url_string
-            };
+/// Shortens `text` for display, keeping the first match visible, and maps the matched
+/// char indices onto byte ranges of the shortened text
+fn truncate_with_matches(text: &str, positions: &[usize]) -> (String, Vec<Range<usize>>) {
+    let char_count = text.chars().count();
+    let start = match positions.first() {
+        Some(&first) if first >= MAX_DISPLAY_CHARS => first
+            .saturating_sub(5)
+            .min(char_count.saturating_sub(MAX_DISPLAY_CHARS)),
+        _ => 0,
+    };
+    let end = (start + MAX_DISPLAY_CHARS).min(char_count);
 
-            div()
-                .underline()
-                .text_color(hsla(240.0, 0.93, 0.83, 1.0))
-                .text_decoration_color(hsla(240.0, 0.93, 0.83, 1.0))
-                .child(url_string)
+    let mut display = String::new();
+    let mut ranges = Vec::new();
+    if start > 0 {
+        display.push_str("...");
+    }
+    for (i, c) in text.chars().enumerate().skip(start).take(end - start) {
+        let byte_start = display.len();
+        display.push(c);
+        if positions.contains(&i) {
+            ranges.push(byte_start..display.len());
         }
-        ClipboardItem::File { path, icon_bytes } => {
+    }
+    if end < char_count {
+        display.push_str("...");
+    }
+
+    (display, ranges)
+}
+
+fn highlighted_text(text: &str, positions: &[usize]) -> StyledText {
+    let (display, ranges) = truncate_with_matches(text, positions);
+    let style = HighlightStyle {
+        color: Some(hsla(45.0 / 360.0, 0.9, 0.65, 1.0)),
+        font_weight: Some(FontWeight::BOLD),
+        ..Default::default()
+    };
+    StyledText::new(display).with_highlights(ranges.into_iter().map(|range| (range, style)))
+}
+
+fn render_item(item: ClipboardItem, positions: &[usize]) -> impl IntoElement {
+    let label = search_text(&item).unwrap_or_default();
+    match item {
+        ClipboardItem::Text(_) => div().child(highlighted_text(&label, positions)),
+        ClipboardItem::Url(_) => div()
+            .underline()
+            .text_color(hsla(240.0, 0.93, 0.83, 1.0))
+            .text_decoration_color(hsla(240.0, 0.93, 0.83, 1.0))
+            .child(highlighted_text(&label, positions)),
+        ClipboardItem::File { icon_bytes, .. } => {
             if let Some(icon_bytes) = icon_bytes {
                 let image = Arc::new(Image::from_bytes(ImageFormat::Png, icon_bytes));
-                div().child(img(image).size_12())
+                div()
+                    .child(img(image).size_12())
+                    .when(!positions.is_empty(), |this| {
+                        this.child(highlighted_text(&label, positions))
+                    })
+            } else if label.is_empty() {
+                div().child("Unknown")
             } else {
-                let filename = path
-                    .file_name()
-                    .and_then(|n| n.to_str())
-                    .unwrap_or("Unknown")
-                    .to_string();
-                div().child(filename)
+                div().child(highlighted_text(&label, positions))
             }
         }
-        ClipboardItem::Image { bytes, format } => {
-            let image = Arc::new(Image::from_bytes(format, bytes));
-            div().child(
-                img(image)
-                    .max_h_32()
-                    .max_w(px(180.0))
-                    .object_fit(ObjectFit::Contain),
-            )
-        }
+        // tagged with its format so it's clear pasting keeps the formatting
+        ClipboardItem::RichText { format, .. } => div()
+            .flex()
+            .gap_1()
+            .items_center()
+            .child(highlighted_text(&label, positions))
+            .child(
+                div()
+                    .px_1()
+                    .rounded_sm()
+                    .bg(hsla(0.0, 0.0, 0.6, 0.2))
+                    .text_color(hsla(0.0, 0.0, 0.9, 0.6))
+                    .child(match format {
+                        RichTextFormat::Rtf => "rtf",
+                        RichTextFormat::Html => "html",
+                    }),
+            ),
+        // the original is only loaded when copied, images it couldn't be decoded for get a label
+        ClipboardItem::Image { thumbnail, .. } => match thumbnail {
+            Some(thumbnail) => {
+                let image = Arc::new(Image::from_bytes(ImageFormat::Png, thumbnail));
+                div().child(
+                    img(image)
+                        .max_h_32()
+                        .max_w(px(180.0))
+                        .object_fit(ObjectFit::Contain),
+                )
+            }
+            None => div().child("Image"),
+        },
     }
 }
 
-fn copy_entry_to_clipboard(entry: ClipboardEntry) {
-    let items = entry.items.clone();
+/// One file of an expanded group, unlike a lone file it's always shown with its name
+fn render_grouped_file(
+    path: &Path,
+    icon_bytes: Option<Vec<u8>>,
+    positions: &[usize],
+) -> impl IntoElement {
+    let label = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string());
+    div()
+        .flex()
+        .gap_1()
+        .items_center()
+        .pl_2()
+        .when_some(icon_bytes, |this, icon_bytes| {
+            let image = Arc::new(Image::from_bytes(ImageFormat::Png, icon_bytes));
+            this.child(img(image).size_4())
+        })
+        .child(highlighted_text(&label, positions))
+}
 
-    run_on_main(move |_mtm| {
-        let pasteboard = unsafe { NSPasteboard::generalPasteboard() };
-        unsafe { pasteboard.clearContents() };
-        items.iter().for_each(|item| match item {
-            ClipboardItem::Text(text) => {
-                let nsstring = NSString::from_str(text);
-                unsafe { pasteboard.setString_forType(&nsstring, NSPasteboardTypeString) };
-            }
-            ClipboardItem::Url(url) => {
-                let nsstring = NSString::from_str(url.as_str());
-                unsafe { pasteboard.setString_forType(&nsstring, NSPasteboardTypeURL) };
-            }
-            ClipboardItem::Image { bytes, format } => {
-                let nsdata = NSData::from_vec(bytes.clone());
-                let ns_jpeg = NSString::from_str(NSPASTEBOARD_TYPE_JPEG);
-                let ns_gif = NSString::from_str(NSPASTEBOARD_TYPE_GIF);
-                let nsimagetype = match format {
-                    ImageFormat::Png => Some(unsafe { NSPasteboardTypePNG }),
-                    ImageFormat::Tiff => Some(unsafe { NSPasteboardTypeTIFF }),
-                    ImageFormat::Jpeg => Some(ns_jpeg.as_ref()),
-                    ImageFormat::Gif => Some(ns_gif.as_ref()),
-                    _ => None,
-                };
-                if let Some(nsimagetype) = nsimagetype {
-                    unsafe { pasteboard.setData_forType(Some(&nsdata), nsimagetype) };
-                }
-            }
-            ClipboardItem::File { path, icon_bytes } => {
-                let url = match url::Url::from_file_path(path) {
-                    Ok(url) => url,
-                    Err(_) => return,
-                };
-                let nsstring = NSString::from_str(url.as_str());
-                unsafe { pasteboard.setString_forType(&nsstring, NSPasteboardTypeFileURL) };
-            }
-        });
-    })
+/// Formats a byte count for display, e.g. `1.5 MB`
+fn format_size(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
+fn render_source(source: SourceApp) -> impl IntoElement {
+    let name = source.display_name().to_string();
+    div()
+        .flex()
+        .gap_1()
+        .items_center()
+        .when_some(source.icon_bytes, |this, icon_bytes| {
+            let image = Arc::new(Image::from_bytes(ImageFormat::Png, icon_bytes));
+            this.child(img(image).size_3())
+        })
+        .child(name)
 }
 
 impl View {
-    pub fn new(cx: &mut App) -> Self {
+    pub fn new(cx: &mut App, history: History, backend: Backend, store: Store) -> Self {
         Self {
+            history,
+            store,
             snapshot: Vec::new(),
+            matches: Vec::new(),
+            query: String::new(),
+            searching: false,
             cur_idx: 0,
             focus_handle: cx.focus_handle(),
             scroll_handle: ScrollHandle::new(),
+            backend,
+            expanded: HashSet::new(),
+            history_size: 0,
         }
     }
 
+    fn copy_entry_to_clipboard(&self, entry: &ClipboardEntry) {
+        self.backend.write_entry(entry);
+    }
+
+    fn selected_entry(&self) -> Option<&ClipboardEntry> {
+        self.matches
+            .get(self.cur_idx)
+            .map(|m| &self.snapshot[m.entry_idx])
+    }
+
     fn move_down(&mut self) {
-        self.cur_idx = (self.cur_idx + 1) % self.snapshot.len();
+        if !self.matches.is_empty() {
+            self.cur_idx = (self.cur_idx + 1) % self.matches.len();
+        }
     }
 
     fn move_up(&mut self) {
-        self.cur_idx = (self.cur_idx + self.snapshot.len() - 1) % self.snapshot.len();
+        if !self.matches.is_empty() {
+            self.cur_idx = (self.cur_idx + self.matches.len() - 1) % self.matches.len();
+        }
+    }
+
+    fn update_matches(&mut self) {
+        let snapshot = &self.snapshot;
+        self.matches = search(snapshot, &self.query);
+        // stable sort keeps the search order within the pinned and unpinned sections
+        self.matches.sort_by_key(|m| !snapshot[m.entry_idx].pinned);
+        if self.cur_idx >= self.matches.len() {
+            self.cur_idx = 0;
+        }
+    }
+
+    fn set_query(&mut self, query: String) {
+        self.query = query;
+        self.cur_idx = 0;
+        self.update_matches();
+        self.scroll_handle.scroll_to_item(0);
     }
 
-    pub fn update_snapshot(&mut self, history: History) {
-        let locked = history.lock().unwrap();
+    pub fn update_snapshot(&mut self) {
+        let locked = self.history.lock().unwrap();
         self.snapshot = locked.clone();
+        drop(locked);
+        self.history_size = history_size(&self.snapshot);
+        self.update_matches();
+    }
+
+    /// Title shown above the first entry of the pinned and recent sections, if anything is pinned
+    fn section_title(&self, i: usize) -> Option<&'static str> {
+        let pinned = |i: usize| self.snapshot[self.matches[i].entry_idx].pinned;
+        if !pinned(0) {
+            None
+        } else if i == 0 {
+            Some("pinned")
+        } else if pinned(i - 1) != pinned(i) {
+            Some("recent")
+        } else {
+            None
+        }
+    }
+
+    fn toggle_pin(&mut self, cx: &mut Context<Self>) {
+        let Some(id) = self.selected_entry().map(|entry| entry.id) else {
+            return;
+        };
+
+        let mut history = self.history.lock().unwrap();
+        if let Some(entry) = history.iter_mut().find(|entry| entry.id == id) {
+            entry.pinned = !entry.pinned;
+        }
+        save_in_background(
+            cx.background_executor(),
+            self.store.clone(),
+            history.clone(),
+        );
+        drop(history);
+
+        // keep the toggled entry selected as it moves between sections
+        self.update_snapshot();
+        if let Some(idx) = self
+            .matches
+            .iter()
+            .position(|m| self.snapshot[m.entry_idx].id == id)
+        {
+            self.cur_idx = idx;
+        }
+        self.scroll_handle.scroll_to_item(self.cur_idx);
+    }
+
+    fn reverse_stack_order(&self, cx: &mut Context<Self>) {
+        let order = cx.global::<PasteStack>().order().reversed();
+        set_stack_order(cx, &self.history, &self.backend, order);
+    }
+
+    fn toggle_expanded(&mut self, id: u64) {
+        if !self.expanded.remove(&id) {
+            self.expanded.insert(id);
+        }
     }
 
     pub fn focus_handle(&self) -> FocusHandle {
         self.focus_handle.clone()
     }
+
+    /// Handles keys while the search field is active, returns false for keys it doesn't use
+    fn handle_search_key(&mut self, event: &KeyDownEvent) -> bool {
+        let keystroke = &event.keystroke;
+        match keystroke.key.as_str() {
+            "escape" => {
+                self.searching = false;
+                self.set_query(String::new());
+            }
+            "backspace" => {
+                let mut query = self.query.clone();
+                query.pop();
+                self.set_query(query);
+            }
+            "enter" | "up" | "down" => return false,
+            _ => {
+                let Some(key_char) = keystroke.key_char.as_ref() else {
+                    return false;
+                };
+                if keystroke.modifiers.control || keystroke.modifiers.platform {
+                    return false;
+                }
+                self.set_query(format!("{}{}", self.query, key_char));
+            }
+        }
+        true
+    }
 }
 
 impl Render for View {
     fn render(&mut self, _window: &mut Window, cx: &mut Context<Self>) -> impl IntoElement {
+        let search_field = if self.searching || !self.query.is_empty() {
+            div()
+                .text_color(hsla(0.0, 0.0, 0.9, 1.0))
+                .child(format!("/{}", self.query))
+        } else {
+            div()
+                .text_color(hsla(0.0, 0.0, 0.9, 0.5))
+                .child("press / to search")
+        };
+
+        let usage = format!(
+            "{} / {}",
+            format_size(self.history_size),
+            format_size(cx.global::<Config>().history_byte_limit)
+        );
+
+        let stack = cx.global::<PasteStack>();
+        let queued = stack.queued();
+        let stack_status = stack.is_active().then(|| {
+            format!(
+                "paste stack, {} · {} queued · o to flip",
+                stack.order().label(),
+                queued.len()
+            )
+        });
+
         div()
+            .flex()
             .flex_col()
             .track_focus(&self.focus_handle())
             .gap_2()
@@ -160,21 +374,47 @@ impl Render for View {
             .bg(hsla(0.0, 0.0, 0.08, 0.5))
             .text_xs()
             .on_key_down(cx.listener(|this, event: &KeyDownEvent, window, cx| {
+                if this.searching && this.handle_search_key(event) {
+                    cx.notify();
+                    return;
+                }
+
                 match event.keystroke.key.as_str() {
-                    "j" => {
+                    "/" => {
+                        this.searching = true;
+                        cx.notify();
+                    }
+                    "p" => {
+                        this.toggle_pin(cx);
+                        cx.notify();
+                    }
+                    "s" => toggle_paste_stack(cx),
+                    "o" if cx.global::<PasteStack>().is_active() => this.reverse_stack_order(cx),
+                    "space" => {
+                        if let Some(id) = this.selected_entry().map(|entry| entry.id) {
+                            this.toggle_expanded(id);
+                            cx.notify();
+                        }
+                    }
+                    "j" | "down" => {
                         this.move_down();
                         this.scroll_handle.scroll_to_item(this.cur_idx);
                         cx.notify();
                     }
-                    "k" => {
+                    "k" | "up" => {
                         this.move_up();
                         this.scroll_handle.scroll_to_item(this.cur_idx);
                         cx.notify();
                     }
                     "enter" => {
-                        let entry = this.snapshot.get(this.cur_idx).unwrap();
-                        copy_entry_to_clipboard(entry.clone());
-                        window.remove_window();
+                        if let Some(entry) = this.selected_entry() {
+                            if event.keystroke.modifiers.shift {
+                                this.copy_entry_to_clipboard(&entry.to_plain_text());
+                            } else {
+                                this.copy_entry_to_clipboard(entry);
+                            }
+                            window.remove_window();
+                        }
                     }
                     "escape" => {
                         cx.hide();
@@ -184,38 +424,213 @@ impl Render for View {
                 }
             }))
             .p_2()
-            .id("history")
-            .overflow_y_scroll()
-            .track_scroll(&self.scroll_handle.clone())
-            .children(self.snapshot.iter().enumerate().map(|(i, entry)| {
-                let items = entry.items.clone();
-                let timestamp = entry.timestamp.format("%Y-%m-%d %H:%M:%S").to_string();
+            .child(
                 div()
-                    .py_1()
+                    .flex()
+                    .justify_between()
                     .px_2()
-                    .flex_col()
-                    .w_full()
-                    .when(self.cur_idx == i, |style| {
-                        style.bg(hsla(0.0, 0.0, 0.6, 0.1))
-                    })
-                    .id(("outer", i))
-                    .on_click(cx.listener(move |this, _event, window, _cx| {
-                        let entry = this.snapshot.get(i).unwrap();
-                        copy_entry_to_clipboard(entry.clone());
-                        window.remove_window();
-                    }))
+                    .py_1()
                     .rounded_lg()
-                    .hover(|style| {
-                        style
-                            .bg(hsla(0.0, 0.0, 0.6, 0.1))
-                            .cursor(CursorStyle::PointingHand)
-                    })
-                    .child(
+                    .bg(hsla(0.0, 0.0, 0.6, 0.1))
+                    .child(search_field)
+                    .child(div().text_color(hsla(0.0, 0.0, 0.9, 0.5)).child(usage)),
+            )
+            .when_some(stack_status, |this, status| {
+                this.child(
+                    div()
+                        .px_2()
+                        .text_color(hsla(45.0 / 360.0, 0.9, 0.65, 1.0))
+                        .child(status),
+                )
+            })
+            .child(
+                div()
+                    .flex_1()
+                    .id("history")
+                    .overflow_y_scroll()
+                    .track_scroll(&self.scroll_handle.clone())
+                    .children(self.matches.iter().enumerate().map(|(i, m)| {
+                        let entry = &self.snapshot[m.entry_idx];
+                        let entry_idx = m.entry_idx;
+                        let entry_id = entry.id;
+                        let items = entry.items.clone();
+                        let is_file =
+                            |item: &ClipboardItem| matches!(item, ClipboardItem::File { .. });
+                        let file_count = items.iter().filter(|item| is_file(item)).count();
+                        // several files are grouped, listed only when expanded or one of them matches a search
+                        let grouped = file_count > 1;
+                        let expanded = self.expanded.contains(&entry_id)
+                            || m.item_idx.is_some_and(|item_idx| {
+                                !m.positions.is_empty() && is_file(&items[item_idx])
+                            });
+                        let group_icon = items.iter().find_map(|item| match item {
+                            ClipboardItem::File { icon_bytes, .. } => icon_bytes.clone(),
+                            _ => None,
+                        });
+                        let has_rich_text = items
+                            .iter()
+                            .any(|item| matches!(item, ClipboardItem::RichText { .. }));
+                        let timestamp = entry.timestamp.format("%Y-%m-%d %H:%M:%S").to_string();
+                        let source = entry.source.clone();
+                        // where it is in the paste stack, counting from the one pasted next
+                        let stack_position = queued
+                            .iter()
+                            .position(|&queued| queued == entry_id)
+                            .map(|position| format!("#{}", position + 1));
+                        let positions = |item_idx| {
+                            if m.item_idx == Some(item_idx) {
+                                m.positions.as_slice()
+                            } else {
+                                &[]
+                            }
+                        };
                         div()
-                            .flex_col()
-                            .children(items.iter().map(|item| render_item(item.clone())))
-                            .child(div().text_color(hsla(0.0, 0.0, 0.9, 0.8)).child(timestamp)),
-                    )
-            }))
+                            .when_some(self.section_title(i), |this, title| {
+                                this.child(
+                                    div()
+                                        .px_2()
+                                        .pt_1()
+                                        .text_color(hsla(0.0, 0.0, 0.9, 0.5))
+                                        .child(title),
+                                )
+                            })
+                            .child(
+                                div()
+                                    .py_1()
+                                    .px_2()
+                                    .flex_col()
+                                    .w_full()
+                                    .when(self.cur_idx == i, |style| {
+                                        style.bg(hsla(0.0, 0.0, 0.6, 0.1))
+                                    })
+                                    .id(("outer", i))
+                                    .on_click(cx.listener(move |this, _event, window, _cx| {
+                                        let entry = this.snapshot.get(entry_idx).unwrap();
+                                        this.copy_entry_to_clipboard(entry);
+                                        window.remove_window();
+                                    }))
+                                    .rounded_lg()
+                                    .hover(|style| {
+                                        style
+                                            .bg(hsla(0.0, 0.0, 0.6, 0.1))
+                                            .cursor(CursorStyle::PointingHand)
+                                    })
+                                    .child(
+                                        div()
+                                            .flex_col()
+                                            .children(
+                                                items
+                                                    .iter()
+                                                    .enumerate()
+                                                    .filter(|(_, item)| !grouped || !is_file(item))
+                                                    .map(|(item_idx, item)| {
+                                                        render_item(
+                                                            item.clone(),
+                                                            positions(item_idx),
+                                                        )
+                                                    }),
+                                            )
+                                            .when(grouped, |this| {
+                                                this.child(
+                                                    div()
+                                                        .id(("files", i))
+                                                        .flex()
+                                                        .gap_1()
+                                                        .items_center()
+                                                        .when_some(
+                                                            group_icon,
+                                                            |this, icon_bytes| {
+                                                                let image =
+                                                                    Arc::new(Image::from_bytes(
+                                                                        ImageFormat::Png,
+                                                                        icon_bytes,
+                                                                    ));
+                                                                this.child(img(image).size_12())
+                                                            },
+                                                        )
+                                                        .child(format!(
+                                                            "{} {file_count} files",
+                                                            if expanded { "▾" } else { "▸" }
+                                                        ))
+                                                        .on_click(cx.listener(
+                                                            move |this, _event, _window, cx| {
+                                                                // expanding shouldn't also copy the entry
+                                                                cx.stop_propagation();
+                                                                this.toggle_expanded(entry_id);
+                                                                cx.notify();
+                                                            },
+                                                        )),
+                                                )
+                                            })
+                                            .when(grouped && expanded, |this| {
+                                                this.children(items.iter().enumerate().filter_map(
+                                                    |(item_idx, item)| match item {
+                                                        ClipboardItem::File {
+                                                            path,
+                                                            icon_bytes,
+                                                        } => Some(render_grouped_file(
+                                                            path,
+                                                            icon_bytes.clone(),
+                                                            positions(item_idx),
+                                                        )),
+                                                        _ => None,
+                                                    },
+                                                ))
+                                            })
+                                            .child(
+                                                div()
+                                                    .flex()
+                                                    .gap_1()
+                                                    .items_center()
+                                                    .text_color(hsla(0.0, 0.0, 0.9, 0.8))
+                                                    .when_some(stack_position, |this, position| {
+                                                        this.child(
+                                                            div()
+                                                                .text_color(hsla(
+                                                                    45.0 / 360.0,
+                                                                    0.9,
+                                                                    0.65,
+                                                                    1.0,
+                                                                ))
+                                                                .child(position),
+                                                        )
+                                                    })
+                                                    .child(timestamp)
+                                                    .when_some(source, |this, source| {
+                                                        this.child(render_source(source))
+                                                    }),
+                                            )
+                                            .when(has_rich_text, |this| {
+                                                this.child(
+                                                    div()
+                                                        .id(("plain", i))
+                                                        .text_color(hsla(0.0, 0.0, 0.9, 0.5))
+                                                        .hover(|style| {
+                                                            style.text_color(hsla(
+                                                                0.0, 0.0, 0.9, 1.0,
+                                                            ))
+                                                        })
+                                                        .child("⇧↵ paste as plain text")
+                                                        .on_click(cx.listener(
+                                                            move |this, _event, window, cx| {
+                                                                // the entry itself would be copied with its formatting
+                                                                cx.stop_propagation();
+                                                                let entry = this
+                                                                    .snapshot
+                                                                    .get(entry_idx)
+                                                                    .unwrap()
+                                                                    .to_plain_text();
+                                                                this.copy_entry_to_clipboard(
+                                                                    &entry,
+                                                                );
+                                                                window.remove_window();
+                                                            },
+                                                        )),
+                                                )
+                                            }),
+                                    ),
+                            )
+                    })),
+            )
     }
 }
@@ -0,0 +1,199 @@
+use std::sync::{Arc, Mutex};
+
+use gpui::{TestAppContext, WindowHandle};
+
+use crate::{
+    backend::{ClipboardBackend, MemoryBackend},
+    config::Config,
+    models::{ClipboardEntry, ClipboardItem, History, RichTextFormat},
+    paste_stack::PasteStack,
+    storage::{Storage, StorageError, Store},
+    view::View,
+};
+
+/// Storage that keeps the ids of every snapshot it's asked to save
+struct RecordingStorage {
+    saves: Arc<Mutex<Vec<Vec<u64>>>>,
+}
+
+impl Storage for RecordingStorage {
+    fn load(&mut self) -> Result<Vec<ClipboardEntry>, StorageError> {
+        Ok(Vec::new())
+    }
+
+    fn save(&mut self, entries: &[ClipboardEntry]) -> Result<(), StorageError> {
+        let ids = entries.iter().map(|entry| entry.id).collect();
+        self.saves.lock().unwrap().push(ids);
+        Ok(())
+    }
+}
+
+fn text(text: &str) -> Vec<ClipboardItem> {
+    vec![ClipboardItem::Text(text.to_string())]
+}
+
+/// The panel open over `entries`, newest first, copying to a memory clipboard
+struct Panel {
+    cx: TestAppContext,
+    window: WindowHandle<View>,
+    memory: Arc<MemoryBackend>,
+    history: History,
+    saves: Arc<Mutex<Vec<Vec<u64>>>>,
+}
+
+impl Panel {
+    fn open(entries: Vec<ClipboardEntry>) -> Self {
+        let mut cx = TestAppContext::single();
+        let memory = Arc::new(MemoryBackend::new());
+        let history = History::new(Mutex::new(entries));
+        let saves = Arc::default();
+        let store = Store::new(RecordingStorage {
+            saves: Arc::clone(&saves),
+        });
+        cx.set_global(Config::default());
+        cx.set_global(PasteStack::default());
+        let window = cx.add_window(|window, cx| {
+            let mut view = View::new(cx, history.clone(), memory.clone(), store);
+            view.update_snapshot();
+            window.focus(&view.focus_handle());
+            view
+        });
+        cx.run_until_parked();
+        Self {
+            cx,
+            window,
+            memory,
+            history,
+            saves,
+        }
+    }
+
+    fn press(&mut self, keystrokes: &str) {
+        self.cx.simulate_keystrokes(self.window.into(), keystrokes);
+    }
+
+    fn type_text(&mut self, input: &str) {
+        self.cx.simulate_input(self.window.into(), input);
+    }
+
+    /// Text of the listed entries in display order, and the index of the selected one
+    fn listed(&mut self) -> (Vec<String>, usize) {
+        self.window
+            .update(&mut self.cx, |view, _window, _cx| {
+                let texts = view
+                    .matches
+                    .iter()
+                    .map(|m| plain_text(&view.snapshot[m.entry_idx].items))
+                    .collect();
+                (texts, view.cur_idx)
+            })
+            .unwrap()
+    }
+
+    /// Text of what's on the clipboard
+    fn clipboard(&self) -> String {
+        plain_text(&self.memory.read_items().unwrap())
+    }
+}
+
+fn plain_text(items: &[ClipboardItem]) -> String {
+    items
+        .iter()
+        .map(|item| match item {
+            ClipboardItem::Text(text) => text.clone(),
+            ClipboardItem::RichText { text, .. } => format!("rich {text}"),
+            _ => panic!("expected text"),
+        })
+        .collect()
+}
+
+fn entries(texts: &[&str]) -> Vec<ClipboardEntry> {
+    texts.iter().map(|t| ClipboardEntry::new(text(t))).collect()
+}
+
+#[test]
+fn enter_copies_the_selected_entry() {
+    let mut panel = Panel::open(entries(&["third", "second", "first"]));
+    assert_eq!(
+        panel.listed(),
+        (vec!["third".into(), "second".into(), "first".into()], 0)
+    );
+
+    panel.press("j j j");
+    assert_eq!(
+        panel.listed().1,
+        0,
+        "moving down past the last entry wraps around"
+    );
+    panel.press("k");
+    assert_eq!(panel.listed().1, 2);
+
+    panel.press("k enter");
+    assert_eq!(panel.clipboard(), "second");
+    // copying from the panel leaves history as it is, the monitor records the copy
+    assert_eq!(panel.history.lock().unwrap().len(), 3);
+}
+
+#[test]
+fn shift_enter_copies_rich_text_as_plain_text() {
+    let rich = ClipboardEntry::new(vec![ClipboardItem::RichText {
+        text: "bold".to_string(),
+        data: b"{\\rtf1 \\b bold}".to_vec(),
+        format: RichTextFormat::Rtf,
+    }]);
+    let mut panel = Panel::open(vec![rich]);
+    panel.press("enter");
+    assert_eq!(panel.clipboard(), "rich bold");
+
+    let mut panel = Panel::open(panel.history.lock().unwrap().clone());
+    panel.press("shift-enter");
+    assert_eq!(panel.clipboard(), "bold");
+}
+
+#[test]
+fn search_narrows_the_list_until_escape() {
+    let mut panel = Panel::open(entries(&["apple pie", "banana", "apricot"]));
+    panel.press("/");
+    panel.type_text("ap");
+    assert_eq!(panel.listed().0, ["apple pie", "apricot"]);
+
+    // letters go to the search, so only the arrow keys move the selection
+    panel.press("down");
+    assert_eq!(panel.listed().1, 1);
+    // typing resets the selection to the best match
+    panel.type_text("r");
+    assert_eq!(panel.listed(), (vec!["apricot".into()], 0));
+
+    panel.press("escape");
+    assert_eq!(panel.listed().0, ["apple pie", "banana", "apricot"]);
+}
+
+#[test]
+fn pinning_keeps_the_entry_selected_as_it_moves_up() {
+    let mut panel = Panel::open(entries(&["third", "second", "first"]));
+    panel.press("j j p");
+    assert_eq!(
+        panel.listed(),
+        (vec!["first".into(), "third".into(), "second".into()], 0)
+    );
+
+    let history = panel.history.lock().unwrap().clone();
+    assert!(history[2].pinned);
+    let saved = panel.saves.lock().unwrap().last().cloned();
+    assert_eq!(saved, Some(history.iter().map(|entry| entry.id).collect()));
+}
+
+#[test]
+fn snapshots_only_change_when_updated() {
+    let mut panel = Panel::open(entries(&["second", "first"]));
+    panel.press("j");
+    panel.history.lock().unwrap().truncate(1);
+    assert_eq!(panel.listed().0, ["second", "first"]);
+
+    panel
+        .window
+        .update(&mut panel.cx, |view, _window, _cx| view.update_snapshot())
+        .unwrap();
+    // the selection moves back to the top once its entry is gone
+    assert_eq!(panel.listed(), (vec!["second".into()], 0));
+}